@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::TEMP_PATH;
+
+/// Size (bytes) the cache is trimmed back to, evicting the least recently used files first
+const MAX_CACHE_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+// Locks held while a file is created, keyed by its path in the cache
+static ENTRY_LOCKS: LazyLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+fn cache_dir() -> PathBuf {
+    TEMP_PATH.get().unwrap().join("cache")
+}
+
+/// Hashes the source's path, size and modification time with the variant, so edited sources aren't served stale.
+/// The hash is FNV-1a, which unlike DefaultHasher stays the same across builds, so files are still found after an update
+pub(crate) fn source_hash(source: &str, variant: &str) -> Result<u64, String> {
+    let metadata = fs::metadata(source).map_err(|e| format!("Failed to read {source}: {e}"))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    let fields: [&[u8]; 5] = [
+        source.as_bytes(),
+        &metadata.len().to_le_bytes(),
+        &modified.as_secs().to_le_bytes(),
+        &modified.subsec_nanos().to_le_bytes(),
+        variant.as_bytes(),
+    ];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for field in fields {
+        // A separator after each field keeps e.g. ("ab", "c") and ("a", "bc") apart
+        for byte in field.iter().chain(&[0xff]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    Ok(hash)
+}
+
+fn entry_path(source: &str, variant: &str, extension: &str) -> Result<PathBuf, String> {
+    let hash = source_hash(source, variant)?;
+    Ok(cache_dir().join(format!("{hash:016x}.{extension}")))
+}
+
+// Marks the file as recently used, eviction goes by modification time
+fn touch(path: &Path) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Returns the cached file made from `source` if it has been created
+pub(crate) fn get(source: &str, variant: &str, extension: &str) -> Result<Option<PathBuf>, String> {
+    let path = entry_path(source, variant, extension)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    touch(&path);
+    Ok(Some(path))
+}
+
+/// A cached file, or one still being written to `partial_path` by `task`, which returns where it's moved once complete
+pub(crate) enum Entry {
+    Ready(PathBuf),
+    Creating {
+        partial_path: PathBuf,
+        task: JoinHandle<Result<PathBuf, String>>,
+    },
+}
+
+/// Like `get_or_create`, but creates the file in the background, so it can be read while it's being written
+pub(crate) async fn get_or_spawn<F, Fut>(
+    source: &str,
+    variant: &str,
+    extension: &str,
+    create: F,
+) -> Result<Entry, String>
+where
+    F: FnOnce(PathBuf) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let path = entry_path(source, variant, extension)?;
+    if path.exists() {
+        touch(&path);
+        return Ok(Entry::Ready(path));
+    }
+
+    let partial_path = partial_path(&path, extension);
+    let (source, variant, extension) =
+        (source.to_owned(), variant.to_owned(), extension.to_owned());
+    // Another request creating the same file is waited on as usual, meanwhile its partial file is shared
+    let task =
+        tokio::spawn(async move { get_or_create(&source, &variant, &extension, create).await });
+    Ok(Entry::Creating { partial_path, task })
+}
+
+/// Returns the cached file made from `source`, or creates it by passing `create` the path to write to.
+/// `variant` tells apart files made from the same source, e.g. the stream index and format
+pub(crate) async fn get_or_create<F, Fut>(
+    source: &str,
+    variant: &str,
+    extension: &str,
+    create: F,
+) -> Result<PathBuf, String>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let path = entry_path(source, variant, extension)?;
+
+    // Requests for the same file wait on the first one creating it
+    let lock = Arc::clone(ENTRY_LOCKS.lock().await.entry(path.clone()).or_default());
+    let result = {
+        let _guard = lock.lock().await;
+        create_entry(path.clone(), extension, create).await
+    };
+
+    // The last request for the file removes its lock, so the map doesn't keep one for every file ever cached.
+    // Others clone the lock only while holding the map, so none can pick it up after this check
+    let mut locks = ENTRY_LOCKS.lock().await;
+    if Arc::strong_count(&lock) == 2 {
+        locks.remove(&path);
+    }
+    result
+}
+
+async fn create_entry<F, Fut>(path: PathBuf, extension: &str, create: F) -> Result<PathBuf, String>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    if path.exists() {
+        touch(&path);
+        return Ok(path);
+    }
+
+    fs::create_dir_all(cache_dir()).map_err(|e| e.to_string())?;
+    let partial_path = partial_path(&path, extension);
+    if let Err(e) = create(partial_path.clone()).await {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+    fs::rename(&partial_path, &path).map_err(|e| e.to_string())?;
+
+    evict(&path);
+    Ok(path)
+}
+
+// Files are written under another name first, so a failed or cancelled file isn't served
+fn partial_path(path: &Path, extension: &str) -> PathBuf {
+    path.with_extension(format!("{extension}.part"))
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "part")
+}
+
+// Removes the least recently used files until the cache fits its size cap, keeping the file just added
+fn evict(keep: &Path) {
+    let Ok(entries) = fs::read_dir(cache_dir()) else {
+        return;
+    };
+
+    // Files still being created are left alone, their request renames them once done
+    let mut files: Vec<(PathBuf, u64, SystemTime)> = entries
+        .flatten()
+        .filter(|entry| !is_partial(&entry.path()))
+        .filter_map(|entry| {
+            let metadata = entry
+                .metadata()
+                .ok()
+                .filter(|metadata| metadata.is_file())?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, size, _) in files {
+        if total <= MAX_CACHE_SIZE {
+            break;
+        }
+        // Files still being served may fail to be removed, they're left for next time
+        if path != keep && fs::remove_file(&path).is_ok() {
+            total -= size;
+        }
+    }
+}
+
+/// Removes every cached file, returning the bytes freed
+pub(crate) async fn clear() -> Result<u64, String> {
+    // Holds off lookups while clearing, files still being created are left alone
+    let _locks = ENTRY_LOCKS.lock().await;
+
+    let entries = match fs::read_dir(cache_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut freed = 0;
+    for entry in entries.flatten() {
+        if is_partial(&entry.path()) {
+            continue;
+        }
+        let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if fs::remove_file(entry.path()).is_ok() {
+            freed += size;
+        }
+    }
+    Ok(freed)
+}
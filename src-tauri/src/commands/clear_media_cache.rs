@@ -0,0 +1,7 @@
+use crate::cache;
+
+/// Removes the audio tracks and other files cached from opened videos, returning the bytes freed
+#[tauri::command]
+pub async fn clear_media_cache() -> Result<u64, String> {
+    cache::clear().await
+}
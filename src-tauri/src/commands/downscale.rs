@@ -0,0 +1,167 @@
+use super::{ffprobe_cmd, media_info::parse_frame_rate};
+
+/// Fewest bits per pixel (per frame) that still give watchable video, below it the output turns blocky
+const MIN_BITS_PER_PIXEL: f64 = 0.05;
+
+/// Output heights stepped down through, largest first
+const HEIGHT_LADDER: [u32; 8] = [2160, 1440, 1080, 720, 540, 480, 360, 240];
+
+/// Frame rates high frame rate video steps down to before its resolution does
+const FPS_LADDER: [f64; 2] = [60.0, 30.0];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct VideoSize {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) fps: f64,
+}
+
+impl VideoSize {
+    fn bits_per_pixel(&self, bitrate: f64) -> f64 {
+        // Bitrate is in Kb/s
+        bitrate * 1000.0 / (self.width as f64 * self.height as f64 * self.fps)
+    }
+
+    // Scales down to the given height keeping the aspect ratio, with an even width for the encoder
+    fn with_height(&self, height: u32) -> Self {
+        let width = (self.width as f64 * height as f64 / self.height as f64).round() as u32;
+        Self {
+            width: (width - width % 2).max(2),
+            height,
+            fps: self.fps,
+        }
+    }
+}
+
+/// Resolution and frame rate picked for a size-limited render whose bitrate is too low for the source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct DownscaleStep {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) fps: f64,
+    pub(crate) bits_per_pixel: f64,
+}
+
+/// Reads the resolution and frame rate of the first video stream in the file
+pub(crate) async fn probe_video_size(filepath: &str) -> Result<VideoSize, String> {
+    let stream =
+        ffprobe_cmd::probe_video_stream(filepath, "width,height,avg_frame_rate,r_frame_rate")
+            .await?;
+
+    let dimension = |key: &str| {
+        stream[key]
+            .as_u64()
+            .filter(|size| *size > 0)
+            .map(|size| size as u32)
+            .ok_or_else(|| format!("The video stream has no {key}"))
+    };
+    // Variable frame rate video has no average rate until it's been read through
+    let fps = ["avg_frame_rate", "r_frame_rate"]
+        .iter()
+        .find_map(|key| parse_frame_rate(stream[key].as_str()?))
+        .ok_or("The video stream has no frame rate")?;
+
+    Ok(VideoSize {
+        width: dimension("width")?,
+        height: dimension("height")?,
+        fps,
+    })
+}
+
+/// Steps down the ladder until the bitrate (Kb/s) gives enough bits per pixel,
+/// returning none when the video can be rendered as it is or there's no smaller step.
+/// The lowest step is used if none of them reach the quality floor.
+pub(crate) fn pick_step(size: VideoSize, bitrate: f64) -> Option<DownscaleStep> {
+    if size.bits_per_pixel(bitrate) >= MIN_BITS_PER_PIXEL {
+        return None;
+    }
+
+    // Lowering the frame rate costs less detail than lowering the resolution, so step it down first
+    let frame_rates: Vec<f64> = std::iter::once(size.fps)
+        .chain(FPS_LADDER.into_iter().filter(|fps| *fps < size.fps))
+        .collect();
+    let lowest_fps = frame_rates[frame_rates.len() - 1];
+    let steps = frame_rates
+        .into_iter()
+        .map(|fps| VideoSize { fps, ..size })
+        .chain(
+            HEIGHT_LADDER
+                .into_iter()
+                .filter(|height| *height < size.height)
+                .map(|height| VideoSize {
+                    fps: lowest_fps,
+                    ..size.with_height(height)
+                }),
+        );
+
+    let mut picked = size;
+    for step in steps {
+        picked = step;
+        if step.bits_per_pixel(bitrate) >= MIN_BITS_PER_PIXEL {
+            break;
+        }
+    }
+    if picked == size {
+        return None;
+    }
+
+    Some(DownscaleStep {
+        width: picked.width,
+        height: picked.height,
+        fps: picked.fps,
+        bits_per_pixel: picked.bits_per_pixel(bitrate),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32, fps: f64) -> VideoSize {
+        VideoSize { width, height, fps }
+    }
+
+    // Width, height and frame rate of the picked step
+    fn pick(size: VideoSize, bitrate: f64) -> Option<(u32, u32, f64)> {
+        pick_step(size, bitrate).map(|step| (step.width, step.height, step.fps))
+    }
+
+    #[test]
+    fn keeps_the_source_when_the_bitrate_is_enough() {
+        // 1080p30 needs 3110.4 Kb/s for the quality floor
+        assert_eq!(pick(size(1920, 1080, 30.0), 3111.0), None);
+        assert_eq!(
+            pick(size(1920, 1080, 30.0), 3110.0),
+            Some((1280, 720, 30.0))
+        );
+    }
+
+    #[test]
+    fn lowers_the_frame_rate_before_the_resolution() {
+        assert_eq!(
+            pick(size(1920, 1080, 60.0), 4000.0),
+            Some((1920, 1080, 30.0))
+        );
+    }
+
+    #[test]
+    fn picks_the_largest_height_reaching_the_floor() {
+        // 720p30 needs 1382.4 Kb/s, 540p30 needs 777.6 Kb/s
+        assert_eq!(
+            pick(size(1920, 1080, 30.0), 1383.0),
+            Some((1280, 720, 30.0))
+        );
+        assert_eq!(pick(size(1920, 1080, 30.0), 1382.0), Some((960, 540, 30.0)));
+    }
+
+    #[test]
+    fn falls_back_to_the_lowest_step() {
+        assert_eq!(pick(size(1920, 1080, 60.0), 1.0), Some((426, 240, 30.0)));
+    }
+
+    #[test]
+    fn keeps_the_source_when_there_is_no_smaller_step() {
+        assert_eq!(pick(size(320, 240, 30.0), 1.0), None);
+        assert_eq!(pick(size(320, 240, 60.0), 1.0), Some((320, 240, 30.0)));
+    }
+}
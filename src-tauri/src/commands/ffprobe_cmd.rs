@@ -1,35 +1,261 @@
-use std::{
-    io::Read,
-    process::{Command, Stdio},
-};
+use std::process::Stdio;
 
+use serde::Serialize;
+use serde_json::Value;
+use tokio::process::Command;
+
+use super::media_info::{MediaInfo, ProbeOutput};
 use crate::FFPROBE_PATH;
 
-#[tauri::command]
-pub async fn ffprobe_cmd(filepath: &str) -> Result<String, String> {
-    let mut json = String::new();
+/// Reads the given `stream=` entries of the first video stream in the file
+pub(crate) async fn probe_video_stream(filepath: &str, entries: &str) -> Result<Value, String> {
+    probe_stream(filepath, "v:0", entries)
+        .await?
+        .ok_or_else(|| "The file has no video stream".into())
+}
+
+/// Reads the given `stream=` entries of the first stream matching the specifier, e.g. `v:0` or an index
+pub(crate) async fn probe_stream(
+    filepath: &str,
+    specifier: &str,
+    entries: &str,
+) -> Result<Option<Value>, String> {
+    let mut json = probe_entries(
+        filepath,
+        &["-select_streams", specifier],
+        &format!("stream={entries}"),
+    )
+    .await?;
+    match json["streams"][0].take() {
+        Value::Null => Ok(None),
+        stream => Ok(Some(stream)),
+    }
+}
+
+/// Reads the duration of the file in seconds
+pub(crate) async fn probe_duration(filepath: &str) -> Result<f64, String> {
+    let json = probe_entries(filepath, &[], "format=duration").await?;
+    json["format"]["duration"]
+        .as_str()
+        .and_then(|duration| duration.parse().ok())
+        .ok_or_else(|| "The file has no duration".into())
+}
+
+/// Runs FFPROBE printing the given `-show_entries` as JSON
+pub(crate) async fn probe_entries(
+    filepath: &str,
+    args: &[&str],
+    entries: &str,
+) -> Result<Value, String> {
+    let mut command = ffprobe();
+    command
+        .args(args)
+        .args(["-show_entries", entries, "-of", "json", filepath]);
 
+    let stdout = run(&mut command)
+        .await
+        .map_err(|e| format!("Failed to read {entries}: {e}"))?;
+    serde_json::from_slice(&stdout).map_err(|e| e.to_string())
+}
+
+// FFPROBE printing only errors, so they can be reported when it fails
+fn ffprobe() -> Command {
     let mut command = Command::new(FFPROBE_PATH.get().unwrap());
+    command.args(["-v", "error"]);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+    command
+}
+
+/// Why FFPROBE couldn't read a file, classified from what it printed to stderr
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ProbeError {
+    NotFound {
+        stderr: String,
+    },
+    PermissionDenied {
+        stderr: String,
+    },
+    /// The file is corrupt, truncated or not media
+    InvalidData {
+        stderr: String,
+    },
+    /// The path is a URL whose protocol FFPROBE wasn't built with
+    UnsupportedProtocol {
+        stderr: String,
+    },
+    /// FFPROBE succeeded but printed JSON that couldn't be read
+    InvalidOutput {
+        message: String,
+    },
+    /// Exit code is None when FFPROBE didn't run or was killed
+    Other {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl ProbeError {
+    fn classify(exit_code: Option<i32>, stderr: String) -> Self {
+        // FFPROBE prints the error of opening the input last, after any warnings
+        let last_line = stderr.lines().last().unwrap_or_default();
+        if last_line.contains("No such file or directory") {
+            ProbeError::NotFound { stderr }
+        } else if last_line.contains("Permission denied") {
+            ProbeError::PermissionDenied { stderr }
+        } else if last_line.contains("Invalid data found when processing input")
+            || last_line.contains("moov atom not found")
+            || last_line.contains("End of file")
+        {
+            ProbeError::InvalidData { stderr }
+        } else if last_line.contains("Protocol not found")
+            || last_line.contains("Protocol not on whitelist")
+        {
+            ProbeError::UnsupportedProtocol { stderr }
+        } else {
+            ProbeError::Other { exit_code, stderr }
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeError::NotFound { .. } => write!(f, "The file doesn't exist"),
+            ProbeError::PermissionDenied { .. } => {
+                write!(f, "Permission to read the file was denied")
+            }
+            ProbeError::InvalidData { stderr } => {
+                write!(f, "The file is corrupt or isn't media: {stderr}")
+            }
+            ProbeError::UnsupportedProtocol { stderr } => {
+                write!(f, "The file's protocol isn't supported: {stderr}")
+            }
+            ProbeError::InvalidOutput { message } => {
+                write!(f, "Unexpected FFPROBE output: {message}")
+            }
+            ProbeError::Other {
+                exit_code: Some(code),
+                stderr,
+            } => write!(f, "FFPROBE exited with code {code}: {stderr}"),
+            ProbeError::Other {
+                exit_code: None,
+                stderr,
+            } => write!(f, "{stderr}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProbeError {
+    fn from(e: std::io::Error) -> Self {
+        ProbeError::Other {
+            exit_code: None,
+            stderr: e.to_string(),
+        }
+    }
+}
+
+// Returns the output of FFPROBE, or why it failed
+async fn run(command: &mut Command) -> Result<Vec<u8>, ProbeError> {
+    let output = command.stdin(Stdio::null()).output().await?;
+
+    if !output.status.success() {
+        return Err(ProbeError::classify(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Reads the container, streams and chapters of the file
+#[tauri::command]
+pub async fn ffprobe_cmd(filepath: &str) -> Result<MediaInfo, ProbeError> {
+    let mut command = ffprobe();
     command.args([
-        "-v",
-        "quiet",
         "-print_format",
         "json",
         "-show_format",
         "-show_streams",
+        "-show_chapters",
         filepath,
     ]);
-    #[cfg(target_os = "windows")]
-    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
-    let child = command.stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| e.to_string())?;
 
-    child
-        .stdout
-        .unwrap()
-        .read_to_string(&mut json)
-        .map_err(|e| e.to_string())?;
+    let stdout = run(&mut command).await?;
+    let output: ProbeOutput =
+        serde_json::from_slice(&stdout).map_err(|e| ProbeError::InvalidOutput {
+            message: e.to_string(),
+        })?;
+
+    Ok(output.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_the_error_ffprobe_printed() {
+        let cases = [
+            (
+                "missing.mp4: No such file or directory",
+                ProbeError::NotFound {
+                    stderr: "missing.mp4: No such file or directory".into(),
+                },
+            ),
+            (
+                "locked.mp4: Permission denied",
+                ProbeError::PermissionDenied {
+                    stderr: "locked.mp4: Permission denied".into(),
+                },
+            ),
+            (
+                "notes.txt: Invalid data found when processing input",
+                ProbeError::InvalidData {
+                    stderr: "notes.txt: Invalid data found when processing input".into(),
+                },
+            ),
+            (
+                "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x55e4a7c0f2c0] moov atom not found",
+                ProbeError::InvalidData {
+                    stderr: "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x55e4a7c0f2c0] moov atom not found".into(),
+                },
+            ),
+            (
+                "gopher://host/video.mp4: Protocol not found",
+                ProbeError::UnsupportedProtocol {
+                    stderr: "gopher://host/video.mp4: Protocol not found".into(),
+                },
+            ),
+            (
+                "input.mp4: Cannot allocate memory",
+                ProbeError::Other {
+                    exit_code: Some(1),
+                    stderr: "input.mp4: Cannot allocate memory".into(),
+                },
+            ),
+        ];
+
+        for (stderr, expected) in cases {
+            assert_eq!(ProbeError::classify(Some(1), stderr.into()), expected);
+        }
+    }
 
-    Ok(json)
+    #[test]
+    fn classifies_by_the_error_after_any_warnings() {
+        let stderr = "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x55e4a7c0f2c0] stream 1, offset 0x2f1c8: partial file\n\
+                      [mov,mp4,m4a,3gp,3g2,mj2 @ 0x55e4a7c0f2c0] Could not find codec parameters for stream 1\n\
+                      clip.mp4: Invalid data found when processing input";
+        assert_eq!(
+            ProbeError::classify(Some(1), stderr.into()),
+            ProbeError::InvalidData {
+                stderr: stderr.into()
+            }
+        );
+    }
 }
@@ -0,0 +1,70 @@
+use super::{ffprobe_cmd, render::TrimRange};
+
+/// Looks up the timestamps (s) of the video keyframes between `from` and `to`.
+/// FFPROBE seeks to the keyframe at or before `from`, so it is always included.
+pub(crate) async fn probe_keyframes(
+    filepath: &str,
+    from: f64,
+    to: f64,
+) -> Result<Vec<f64>, String> {
+    // Packet flags tell keyframes apart without having to decode any frames
+    let json = ffprobe_cmd::probe_entries(
+        filepath,
+        &[
+            "-select_streams",
+            "v:0",
+            "-read_intervals",
+            &format!("{from}%{to}"),
+        ],
+        "packet=pts_time,flags",
+    )
+    .await?;
+
+    let mut keyframes: Vec<f64> = json["packets"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|packet| {
+            packet["flags"]
+                .as_str()
+                .is_some_and(|flags| flags.starts_with('K'))
+        })
+        .filter_map(|packet| packet["pts_time"].as_str()?.parse().ok())
+        .collect();
+    // Packets are listed in decoding order, which differs from presentation order with B-frames
+    keyframes.sort_by(f64::total_cmp);
+
+    Ok(keyframes)
+}
+
+/// Moves the start of a trim range back to the keyframe at or before it, where a stream copy can begin
+pub(crate) async fn snap_range(filepath: &str, range: TrimRange) -> Result<TrimRange, String> {
+    let keyframes = probe_keyframes(filepath, range.start, range.end).await?;
+
+    let start = keyframes
+        .iter()
+        .rev()
+        .find(|keyframe| **keyframe <= range.start)
+        .or(keyframes.first())
+        .copied()
+        .unwrap_or(range.start);
+
+    Ok(TrimRange {
+        start: start.max(0.0),
+        end: range.end,
+    })
+}
+
+/// Snaps trim ranges to keyframes, returning the ranges a stream copy will actually produce
+#[tauri::command]
+pub async fn snap_to_keyframes(
+    filepath: String,
+    ranges: Vec<TrimRange>,
+) -> Result<Vec<TrimRange>, String> {
+    let mut snapped = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        snapped.push(snap_range(&filepath, range).await?);
+    }
+    Ok(snapped)
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+
+/// Loudness to normalise audio to with the EBU R128 loudnorm filter
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessTarget {
+    /// Integrated loudness in LUFS
+    integrated: f64,
+    /// Maximum true peak in dBTP
+    true_peak: f64,
+    /// Loudness range in LU
+    #[serde(default = "default_loudness_range")]
+    loudness_range: f64,
+}
+
+fn default_loudness_range() -> f64 {
+    11.0
+}
+
+/// Loudness of an audio stream, measured by the first loudnorm pass
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessMeasurement {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub loudness_range: f64,
+    pub threshold: f64,
+    /// Gain offset loudnorm applies after normalising
+    pub offset: f64,
+}
+
+impl LoudnessMeasurement {
+    // Silent audio measures as -inf, which the second pass can't take
+    fn is_finite(&self) -> bool {
+        [
+            self.integrated,
+            self.true_peak,
+            self.loudness_range,
+            self.threshold,
+            self.offset,
+        ]
+        .iter()
+        .all(|value| value.is_finite())
+    }
+}
+
+// Statistics loudnorm prints with print_format=json, with every value given as a string
+#[derive(Deserialize)]
+struct LoudnormStats {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Sample rate loudnorm's output is resampled to, as it always upsamples to 192kHz
+pub(crate) const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+impl LoudnessTarget {
+    /// Filter measuring the loudness of a stream, printing the measurement to stderr
+    pub(crate) fn measure_filter(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            self.integrated, self.true_peak, self.loudness_range
+        )
+    }
+
+    /// Filter normalising a stream using its measurement
+    pub(crate) fn normalize_filter(&self, measurement: &LoudnessMeasurement) -> Option<String> {
+        measurement.is_finite().then(|| {
+            format!(
+                "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+                self.integrated,
+                self.true_peak,
+                self.loudness_range,
+                measurement.integrated,
+                measurement.true_peak,
+                measurement.loudness_range,
+                measurement.threshold,
+                measurement.offset
+            )
+        })
+    }
+}
+
+/// Reads the measurement loudnorm printed at the end of FFMPEG's stderr
+pub(crate) fn parse_measurement(stderr: &str) -> Result<LoudnessMeasurement, String> {
+    let json = stderr
+        .rfind('{')
+        .and_then(|start| {
+            let end = start + stderr[start..].find('}')?;
+            Some(&stderr[start..=end])
+        })
+        .ok_or("FFMPEG didn't print a loudness measurement")?;
+
+    let stats: LoudnormStats = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid loudness value: {value}"))
+    };
+
+    Ok(LoudnessMeasurement {
+        integrated: parse(&stats.input_i)?,
+        true_peak: parse(&stats.input_tp)?,
+        loudness_range: parse(&stats.input_lra)?,
+        threshold: parse(&stats.input_thresh)?,
+        offset: parse(&stats.target_offset)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEASUREMENT: &str = r#"[Parsed_loudnorm_0 @ 0x5581c0d2a140] 
+{
+	"input_i" : "-23.54",
+	"input_tp" : "-5.12",
+	"input_lra" : "7.10",
+	"input_thresh" : "-33.91",
+	"output_i" : "-16.05",
+	"output_tp" : "-1.50",
+	"output_lra" : "5.60",
+	"output_thresh" : "-26.38",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.05"
+}
+"#;
+
+    #[test]
+    fn parses_the_loudnorm_measurement() {
+        assert_eq!(
+            parse_measurement(MEASUREMENT),
+            Ok(LoudnessMeasurement {
+                integrated: -23.54,
+                true_peak: -5.12,
+                loudness_range: 7.1,
+                threshold: -33.91,
+                offset: 0.05,
+            })
+        );
+    }
+
+    #[test]
+    fn skips_warnings_printed_before_the_measurement() {
+        let stderr = format!(
+            "[aac @ 0x5581c0c8e2c0] Queue input is backward in time\n\
+             [mp4 @ 0x5581c0c8d1c0] Non-monotonous DTS in output stream 0:1; previous: 1024, current: 512; \
+             changing to 1025. This may result in incorrect timestamps in the output file.\n\
+             {MEASUREMENT}"
+        );
+        assert_eq!(
+            parse_measurement(&stderr).map(|measurement| measurement.integrated),
+            Ok(-23.54)
+        );
+    }
+
+    #[test]
+    fn silent_audio_measures_as_infinite() {
+        let stderr = MEASUREMENT
+            .replace("\"-23.54\"", "\"-inf\"")
+            .replace("\"-5.12\"", "\"-inf\"");
+        let measurement = parse_measurement(&stderr).unwrap();
+        assert_eq!(measurement.integrated, f64::NEG_INFINITY);
+        assert!(!measurement.is_finite());
+    }
+
+    #[test]
+    fn fails_without_a_measurement() {
+        let stderr = "[in#0 @ 0x55d0c3a0b2c0] Error opening input: Invalid data found when processing input\n\
+                      Error opening input file input.mp4.\n";
+        assert_eq!(
+            parse_measurement(stderr),
+            Err("FFMPEG didn't print a loudness measurement".to_owned())
+        );
+    }
+
+    #[test]
+    fn fails_on_a_truncated_measurement() {
+        let stderr = &MEASUREMENT[..MEASUREMENT.find("\"input_lra\"").unwrap()];
+        assert!(parse_measurement(stderr).is_err());
+    }
+}
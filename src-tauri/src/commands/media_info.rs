@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// Output of `ffprobe -print_format json -show_format -show_streams -show_chapters`.
+// FFPROBE prints most numbers as strings, which are parsed when converting to MediaInfo
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    #[serde(default)]
+    chapters: Vec<ProbeChapter>,
+    format: ProbeFormat,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeFormat {
+    filename: String,
+    format_name: String,
+    format_long_name: Option<String>,
+    start_time: Option<String>,
+    duration: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    codec_long_name: Option<String>,
+    profile: Option<String>,
+    start_time: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    nb_frames: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    pix_fmt: Option<String>,
+    display_aspect_ratio: Option<String>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    #[serde(default)]
+    disposition: HashMap<String, i64>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeChapter {
+    id: i64,
+    start_time: String,
+    end_time: String,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Kind of media in a stream
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    /// Files embedded in the container, e.g. the fonts of subtitles
+    Attachment,
+    Data,
+    Unknown,
+}
+
+/// A stream of a media file, with the fields FFPROBE knows of it
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamInfo {
+    pub index: u32,
+    pub codec_type: StreamKind,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    /// In seconds
+    pub start_time: Option<f64>,
+    /// In seconds
+    pub duration: Option<f64>,
+    /// In b/s
+    pub bit_rate: Option<u64>,
+    pub frame_count: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    pub display_aspect_ratio: Option<String>,
+    /// Average frame rate, which is only known for variable frame rate video once it's been read through
+    pub avg_frame_rate: Option<f64>,
+    /// Lowest frame rate that can represent every timestamp
+    pub r_frame_rate: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    /// e.g. default, forced or attached_pic
+    pub disposition: HashMap<String, bool>,
+    pub tags: HashMap<String, String>,
+}
+
+/// A chapter of a media file
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterInfo {
+    pub id: i64,
+    /// In seconds
+    pub start: f64,
+    /// In seconds
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// Container, streams and chapters of a media file
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    pub filename: String,
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    /// In seconds
+    pub start_time: Option<f64>,
+    /// In seconds
+    pub duration: Option<f64>,
+    /// In bytes
+    pub size: Option<u64>,
+    /// In b/s
+    pub bit_rate: Option<u64>,
+    pub tags: HashMap<String, String>,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+}
+
+/// Parses frame rates given as a fraction, e.g. 30000/1001. Unknown rates are given as 0/0
+pub(crate) fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/')?;
+    let fps = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (fps.is_finite() && fps > 0.0).then_some(fps)
+}
+
+/// Parses a number FFMPEG or FFPROBE printed, without its unit if any.
+/// Values they don't know are given as N/A, which fail to parse
+pub(crate) fn parse_value<T: std::str::FromStr>(value: Option<&str>, unit: &str) -> Option<T> {
+    value?.trim().trim_end_matches(unit).parse().ok()
+}
+
+impl From<ProbeStream> for StreamInfo {
+    fn from(stream: ProbeStream) -> Self {
+        let codec_type = match stream.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("attachment") => StreamKind::Attachment,
+            Some("data") => StreamKind::Data,
+            _ => StreamKind::Unknown,
+        };
+
+        Self {
+            index: stream.index,
+            codec_type,
+            start_time: parse_value(stream.start_time.as_deref(), ""),
+            duration: parse_value(stream.duration.as_deref(), ""),
+            bit_rate: parse_value(stream.bit_rate.as_deref(), ""),
+            frame_count: parse_value(stream.nb_frames.as_deref(), ""),
+            avg_frame_rate: stream.avg_frame_rate.as_deref().and_then(parse_frame_rate),
+            r_frame_rate: stream.r_frame_rate.as_deref().and_then(parse_frame_rate),
+            sample_rate: parse_value(stream.sample_rate.as_deref(), ""),
+            disposition: stream
+                .disposition
+                .into_iter()
+                .map(|(key, value)| (key, value != 0))
+                .collect(),
+            codec_name: stream.codec_name,
+            codec_long_name: stream.codec_long_name,
+            profile: stream.profile,
+            width: stream.width,
+            height: stream.height,
+            pix_fmt: stream.pix_fmt,
+            display_aspect_ratio: stream.display_aspect_ratio,
+            channels: stream.channels,
+            channel_layout: stream.channel_layout,
+            tags: stream.tags,
+        }
+    }
+}
+
+impl From<ProbeOutput> for MediaInfo {
+    fn from(output: ProbeOutput) -> Self {
+        let format = output.format;
+
+        Self {
+            start_time: parse_value(format.start_time.as_deref(), ""),
+            duration: parse_value(format.duration.as_deref(), ""),
+            size: parse_value(format.size.as_deref(), ""),
+            bit_rate: parse_value(format.bit_rate.as_deref(), ""),
+            filename: format.filename,
+            format_name: format.format_name,
+            format_long_name: format.format_long_name,
+            tags: format.tags,
+            streams: output.streams.into_iter().map(StreamInfo::from).collect(),
+            chapters: output
+                .chapters
+                .into_iter()
+                .filter_map(|chapter| {
+                    Some(ChapterInfo {
+                        id: chapter.id,
+                        start: chapter.start_time.parse().ok()?,
+                        end: chapter.end_time.parse().ok()?,
+                        title: chapter.tags.get("title").cloned(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_frame_rates_given_as_fractions() {
+        assert_eq!(parse_frame_rate("25/1"), Some(25.0));
+        assert_eq!(parse_frame_rate("30000/1001"), Some(30000.0 / 1001.0));
+    }
+
+    #[test]
+    fn unknown_frame_rates_are_none() {
+        assert_eq!(parse_frame_rate("0/0"), None);
+        assert_eq!(parse_frame_rate("0/1"), None);
+        assert_eq!(parse_frame_rate("25"), None);
+        assert_eq!(parse_frame_rate("N/A"), None);
+    }
+
+    #[test]
+    fn parses_values_with_their_unit() {
+        assert_eq!(parse_value(Some(" 12.5 "), ""), Some(12.5));
+        assert_eq!(parse_value(Some("1.02x"), "x"), Some(1.02));
+        assert_eq!(parse_value::<f64>(Some("N/A"), "x"), None);
+        assert_eq!(parse_value::<f64>(None, ""), None);
+    }
+
+    #[test]
+    fn converts_durations_and_unknown_values() {
+        let output: ProbeOutput = serde_json::from_value(serde_json::json!({
+            "streams": [{
+                "index": 0,
+                "codec_type": "video",
+                "codec_name": "h264",
+                "duration": "12.345000",
+                "bit_rate": "N/A",
+                "avg_frame_rate": "0/0",
+                "r_frame_rate": "24000/1001",
+                "disposition": { "default": 1, "attached_pic": 0 },
+            }],
+            "chapters": [{
+                "id": 0,
+                "start_time": "0.000000",
+                "end_time": "6.000000",
+                "tags": { "title": "Intro" },
+            }],
+            "format": {
+                "filename": "input.mkv",
+                "format_name": "matroska,webm",
+                "duration": "12.400000",
+                "size": "1048576",
+                "bit_rate": "N/A",
+            },
+        }))
+        .unwrap();
+        let info = MediaInfo::from(output);
+
+        assert_eq!(info.duration, Some(12.4));
+        assert_eq!(info.size, Some(1048576));
+        assert_eq!(info.bit_rate, None);
+        let stream = &info.streams[0];
+        assert_eq!(stream.codec_type, StreamKind::Video);
+        assert_eq!(stream.duration, Some(12.345));
+        assert_eq!(stream.bit_rate, None);
+        assert_eq!(stream.avg_frame_rate, None);
+        assert_eq!(stream.r_frame_rate, Some(24000.0 / 1001.0));
+        assert!(stream.disposition["default"]);
+        assert!(!stream.disposition["attached_pic"]);
+        assert_eq!(
+            info.chapters,
+            [ChapterInfo {
+                id: 0,
+                start: 0.0,
+                end: 6.0,
+                title: Some("Intro".into()),
+            }]
+        );
+    }
+}
@@ -1,7 +1,15 @@
+pub mod clear_media_cache;
 pub mod close_splashscreen;
+pub mod downscale;
 pub mod ffprobe_cmd;
 pub mod get_encoders;
 pub mod get_hwaccels;
+pub mod keyframes;
+pub mod loudness;
+pub mod media_info;
+pub mod preview;
 pub mod render;
+pub mod render_queue;
 pub mod show_in_folder;
+pub mod smart_cut;
 pub mod toggle_fullscreen;
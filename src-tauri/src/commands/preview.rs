@@ -0,0 +1,18 @@
+use tauri::ipc::Channel;
+
+use crate::protocols::preview::{self, PreviewSource};
+
+/// Makes a proxy of the file if the player can't play it, sending the transcode's progress to `on_progress`.
+/// Proxies are played from `preview://`, `segmented` plays them from an HLS playlist without waiting for a whole proxy
+#[tauri::command]
+pub async fn prepare_preview(
+    filepath: String,
+    segmented: bool,
+    on_progress: Channel,
+) -> Result<PreviewSource, String> {
+    preview::prepare_preview(&filepath, segmented, |progress| {
+        // The frontend may have opened another file since
+        let _ = on_progress.send(progress);
+    })
+    .await
+}
@@ -1,179 +1,2160 @@
 use std::{
     collections::HashMap,
+    path::{Path, PathBuf},
     process::Stdio,
-    sync::{atomic::AtomicU32, LazyLock},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, LazyLock,
+    },
 };
 
-use tauri::{Manager, Window};
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
 use tokio::{
     io::{AsyncBufReadExt, AsyncReadExt, BufReader},
     process::Command,
-    sync::Mutex,
+    sync::{oneshot, Mutex},
 };
 
-use crate::FFMPEG_PATH;
+use super::{
+    downscale::{self, VideoSize},
+    ffprobe_cmd, keyframes,
+    loudness::{self, LoudnessMeasurement, LoudnessTarget},
+    media_info::parse_value,
+    smart_cut::{self, Segment, SegmentEncoder, KEYFRAME_EPSILON},
+};
+use crate::{
+    filtergraph::{FilterGraph, MediaKind, MixInput, Stream},
+    FFMPEG_PATH, TEMP_PATH,
+};
+
+struct RenderTask {
+    canceller: oneshot::Sender<()>,
+    accept_current: Arc<AtomicBool>,
+    status: Arc<std::sync::Mutex<RenderStatus>>,
+}
+
+/// Current state of a render task, as reported by `list_renders`
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderStatus {
+    id: u32,
+    input_filepath: String,
+    output_filepath: String,
+    attempt: u32,
+    pass: Option<u8>,
+    /// Last progress sent for the task, none until its first FFMPEG process starts
+    progress: Option<RenderProgress>,
+}
+
+static RENDER_TASKS: LazyLock<Mutex<HashMap<u32, RenderTask>>> = LazyLock::new(Default::default);
+static NEXT_RENDER_TASK: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderSettings {
+    input_filepath: String,
+    output_filepath: String,
+    v_codec_id: String,
+    a_codec_id: String,
+    override_file: bool,
+    audio_tracks: Vec<u32>,
+    /// Gain and pan of the audio tracks, tracks missing from it are mixed in as they are
+    #[serde(default)]
+    audio_mix: Vec<AudioTrackMix>,
+    /// Map each audio track to its own stream instead of mixing them together
+    #[serde(default)]
+    separate_audio_tracks: bool,
+    /// Normalise the loudness of the audio with a measurement pass before rendering
+    #[serde(default)]
+    loudness: Option<LoudnessTarget>,
+    /// Measured loudness of each audio output, filled in by the measurement pass
+    #[serde(skip)]
+    loudness_measurements: Vec<LoudnessMeasurement>,
+    /// Channel layout of the mixed audio, e.g. mono or 5.1. Several tracks are mixed to stereo if none
+    #[serde(default)]
+    channel_layout: Option<String>,
+    /// Fades at the start and end of the output, smoothing over the hard cuts of the trim
+    #[serde(default)]
+    fades: Fades,
+    /// Subtitle tracks of the input to keep, as their own streams or burned into the video
+    #[serde(default)]
+    subtitle_tracks: Vec<SubtitleTrack>,
+    /// Position of the burned in track among the input's subtitle streams, found before rendering
+    #[serde(skip)]
+    burn_in_position: Option<u32>,
+    /// Chapters of the input to write to the output, which are cut and joined like the trim ranges.
+    /// The input's chapters are left out if none
+    #[serde(default)]
+    chapters: Vec<Chapter>,
+    /// FFMETADATA file the trimmed chapters are read from, written before rendering
+    #[serde(skip)]
+    chapter_list: Option<PathBuf>,
+    /// Rate control arguments, which may contain `{TARGET_BITRATE}`-style templates
+    codec_rate_control: Vec<String>,
+    target_bitrate: f64,
+    min_bitrate: f64,
+    max_bitrate: f64,
+    buf_size: f64,
+    crf_value: Option<f64>,
+    #[serde(default)]
+    trim_start: f64,
+    #[serde(default)]
+    trim_end: f64,
+    /// Ranges to concatenate into the output, used instead of the single trim range when given
+    #[serde(default)]
+    trim_ranges: Vec<TrimRange>,
+    #[serde(default)]
+    two_pass: bool,
+    #[serde(default)]
+    mode: RenderMode,
+    /// Output width, or the source width if none. Odd dimensions are rounded down to be even
+    #[serde(default)]
+    width: Option<u32>,
+    #[serde(default)]
+    height: Option<u32>,
+    /// Keep the aspect ratio of the source, fitting the video inside the width and height when both are given
+    #[serde(default = "default_lock_ratio")]
+    lock_ratio: bool,
+    #[serde(default)]
+    scale_algorithm: ScaleAlgorithm,
+    /// Size of the source video, probed before rendering for the hardware scalers to be given exact dimensions
+    #[serde(skip)]
+    source_size: Option<VideoSize>,
+    /// Output frame rate, or the source frame rate if none
+    #[serde(default)]
+    fps: Option<f64>,
+    /// Audio bitrate in Kb/s, or the encoder's default if none
+    #[serde(default)]
+    audio_bitrate: Option<f64>,
+    #[serde(default)]
+    sample_rate: Option<u32>,
+    /// Audio channels, or the channels of the mix if none
+    #[serde(default)]
+    audio_channels: Option<u32>,
+}
+
+fn default_lock_ratio() -> bool {
+    true
+}
+
+// Rounds a dimension down to be even, as encoders need for chroma subsampling
+fn even(size: u32) -> u32 {
+    (size - size % 2).max(2)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TrimRange {
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrackMix {
+    index: u32,
+    /// Linear volume multiplier, 0 mutes the track
+    #[serde(default = "default_gain")]
+    gain: f64,
+    /// Stereo position from -1 (left) to 1 (right)
+    #[serde(default)]
+    pan: Option<f64>,
+    /// Codec of the track when kept separate, or the audio codec of the render if none
+    #[serde(default)]
+    codec_id: Option<String>,
+    /// Bitrate (Kb/s) of the track when kept separate, or the audio bitrate of the render if none
+    #[serde(default)]
+    bitrate: Option<f64>,
+}
+
+/// Audio stream written to the output
+struct AudioOutput {
+    stream: Stream,
+    /// Index of the input track when it is kept separate from the others
+    track: Option<u32>,
+}
+
+fn default_gain() -> f64 {
+    1.0
+}
+
+/// Fade durations in seconds, relative to the trimmed output. 0 leaves out the fade
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Fades {
+    audio_in: f64,
+    audio_out: f64,
+    /// Video fades from and to black
+    video_in: f64,
+    video_out: f64,
+}
+
+impl Fades {
+    fn has_video(&self) -> bool {
+        self.video_in > 0.0 || self.video_out > 0.0
+    }
+
+    fn has_audio(&self) -> bool {
+        self.audio_in > 0.0 || self.audio_out > 0.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleTrack {
+    index: u32,
+    /// Draw the subtitles onto the video instead of copying them as a stream
+    #[serde(default)]
+    burn_in: bool,
+}
+
+/// A chapter in seconds, of the input or of the output once trimmed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Chapter {
+    start: f64,
+    end: f64,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RenderMode {
+    /// Re-encode the streams with the chosen codecs
+    #[default]
+    Encode,
+    /// Copy the streams losslessly, starting each trim range at a keyframe
+    Copy,
+    /// Re-encode only the partial GOPs at the edges of each trim range and copy the rest
+    Smart,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleAlgorithm {
+    FastBilinear,
+    Bilinear,
+    #[default]
+    Bicubic,
+    Neighbor,
+    Area,
+    Lanczos,
+    Spline,
+}
+
+impl ScaleAlgorithm {
+    // Value of the flags option of the software scaler
+    fn flag(self) -> &'static str {
+        match self {
+            ScaleAlgorithm::FastBilinear => "fast_bilinear",
+            ScaleAlgorithm::Bilinear => "bilinear",
+            ScaleAlgorithm::Bicubic => "bicubic",
+            ScaleAlgorithm::Neighbor => "neighbor",
+            ScaleAlgorithm::Area => "area",
+            ScaleAlgorithm::Lanczos => "lanczos",
+            ScaleAlgorithm::Spline => "spline",
+        }
+    }
+
+    // Value of the interp_algo option of scale_cuda, which only has some of the algorithms
+    fn cuda_interp_algo(self) -> Option<&'static str> {
+        match self {
+            ScaleAlgorithm::Neighbor => Some("nearest"),
+            ScaleAlgorithm::Bilinear | ScaleAlgorithm::FastBilinear => Some("bilinear"),
+            ScaleAlgorithm::Bicubic => Some("bicubic"),
+            ScaleAlgorithm::Lanczos => Some("lanczos"),
+            ScaleAlgorithm::Area | ScaleAlgorithm::Spline => None,
+        }
+    }
+}
+
+/// Scale filter running on the same GPU as a hardware encoder, so frames never leave it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HardwareScaler {
+    Cuda,
+    Qsv,
+    Vaapi,
+}
+
+impl HardwareScaler {
+    fn for_encoder(v_codec_id: &str) -> Option<Self> {
+        if v_codec_id.ends_with("_nvenc") {
+            Some(HardwareScaler::Cuda)
+        } else if v_codec_id.ends_with("_qsv") {
+            Some(HardwareScaler::Qsv)
+        } else if v_codec_id.ends_with("_vaapi") {
+            Some(HardwareScaler::Vaapi)
+        } else {
+            None
+        }
+    }
+
+    fn hwaccel(self) -> &'static str {
+        match self {
+            HardwareScaler::Cuda => "cuda",
+            HardwareScaler::Qsv => "qsv",
+            HardwareScaler::Vaapi => "vaapi",
+        }
+    }
+
+    fn filter(self) -> &'static str {
+        match self {
+            HardwareScaler::Cuda => "scale_cuda",
+            HardwareScaler::Qsv => "scale_qsv",
+            HardwareScaler::Vaapi => "scale_vaapi",
+        }
+    }
+}
+
+/// Audio bitrate (Kb/s) given to size-limited renders that don't set one, so the size of the audio is known
+const DEFAULT_AUDIO_BITRATE: f64 = 128.0;
+
+const HARDWARE_ENCODER_SUFFIXES: [&str; 5] = ["_nvenc", "_amf", "_qsv", "_videotoolbox", "_vaapi"];
+
+const TWO_PASS_ENCODERS: [&str; 4] = ["libx264", "libx265", "libvpx-vp9", "libaom-av1"];
+
+/// Subtitle codecs made of images, which the subtitles filter can't draw
+const BITMAP_SUBTITLE_CODECS: [&str; 4] =
+    ["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
+/// File in the temp directory, removed when dropped
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    // Claims the name for a file FFMPEG will write
+    fn new(name: String) -> Self {
+        Self {
+            path: TEMP_PATH.get().unwrap().join(name),
+        }
+    }
+
+    fn write(name: String, contents: String) -> std::io::Result<Self> {
+        let file = Self::new(name);
+        std::fs::write(&file.path, contents)?;
+        Ok(file)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Concat demuxer script listing the files (or ranges of them) to join
+fn concat_script(files: &[(&Path, Option<TrimRange>)]) -> String {
+    let mut script = String::from("ffconcat version 1.0\n");
+    for (filepath, range) in files {
+        // Single quotes can't be escaped inside a quoted string, so close the quotes around them
+        let filepath = filepath.to_string_lossy().replace('\'', "'\\''");
+        script.push_str(&format!("file '{filepath}'\n"));
+
+        if let Some(range) = range {
+            script.push_str(&format!(
+                "inpoint {}\noutpoint {}\n",
+                range.start, range.end
+            ));
+        }
+    }
+    script
+}
+
+// FFMETADATA file with the chapters of the output
+fn chapter_metadata(chapters: &[Chapter]) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        metadata.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\n",
+            (chapter.start * 1000.0).round(),
+            (chapter.end * 1000.0).round()
+        ));
+        if let Some(title) = &chapter.title {
+            // Characters with a meaning in the file are escaped with a backslash
+            let title: String = title
+                .chars()
+                .flat_map(|c| {
+                    ['=', ';', '#', '\\', '\n']
+                        .contains(&c)
+                        .then_some('\\')
+                        .into_iter()
+                        .chain([c])
+                })
+                .collect();
+            metadata.push_str(&format!("title={title}\n"));
+        }
+    }
+    metadata
+}
+
+/// FFMPEG pass log shared by both passes of a two-pass render, removed from the temp directory when dropped
+struct PassLog {
+    name: String,
+    analysed: bool,
+}
+
+impl PassLog {
+    fn new(task_id: u32) -> Self {
+        Self {
+            name: format!("passlog-{task_id}"),
+            analysed: false,
+        }
+    }
+}
+
+/// Suffixes encoders append to the pass log name: -0.log from -passlogfile, .log from x265's stats,
+/// and the temporary files they're written through
+const PASS_LOG_SUFFIXES: [&str; 8] = [
+    "-0.log",
+    "-0.log.temp",
+    "-0.log.mbtree",
+    "-0.log.mbtree.temp",
+    ".log",
+    ".log.temp",
+    ".log.cutree",
+    ".log.cutree.temp",
+];
+
+impl Drop for PassLog {
+    fn drop(&mut self) {
+        let temp_path = TEMP_PATH.get().unwrap();
+        for suffix in PASS_LOG_SUFFIXES {
+            let _ = std::fs::remove_file(temp_path.join(format!("{}{suffix}", self.name)));
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum EncodePass<'a> {
+    First { log_name: &'a str },
+    Second { log_name: &'a str },
+}
+
+impl RenderSettings {
+    fn trim_ranges(&self) -> Vec<TrimRange> {
+        if self.trim_ranges.is_empty() {
+            vec![TrimRange {
+                start: self.trim_start,
+                end: self.trim_end,
+            }]
+        } else {
+            self.trim_ranges.clone()
+        }
+    }
+
+    /// Duration of the output in seconds
+    fn duration(&self) -> f64 {
+        self.trim_ranges()
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    pub(crate) fn output_filepath(&self) -> &str {
+        &self.output_filepath
+    }
+
+    /// Whether the video is encoded on the GPU, which can only take one render at a time
+    pub(crate) fn uses_hardware_encoder(&self) -> bool {
+        HARDWARE_ENCODER_SUFFIXES
+            .iter()
+            .any(|suffix| self.v_codec_id.ends_with(suffix))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // Failed renders remove their output, so never start one that could clobber an existing file
+        if !self.override_file && Path::new(&self.output_filepath).exists() {
+            return Err(format!("{} already exists", self.output_filepath));
+        }
+        if self
+            .trim_ranges()
+            .iter()
+            .any(|range| range.start < 0.0 || range.end <= range.start)
+        {
+            return Err("Trim ranges must start at or after 0 and end after they start".into());
+        }
+        if self.width == Some(0) || self.height == Some(0) {
+            return Err("The output resolution must be at least 1x1".into());
+        }
+        if self.audio_bitrate.is_some_and(|bitrate| bitrate <= 0.0)
+            || self.sample_rate == Some(0)
+            || self.audio_channels == Some(0)
+        {
+            return Err("The audio bitrate, sample rate and channels must be above 0".into());
+        }
+        if self.audio_mix.iter().any(|mix| {
+            mix.gain < 0.0
+                || mix.pan.is_some_and(|pan| !(-1.0..=1.0).contains(&pan))
+                || mix.bitrate.is_some_and(|bitrate| bitrate <= 0.0)
+        }) {
+            return Err(
+                "Audio track gains must be at least 0, pans between -1 and 1 and bitrates above 0"
+                    .into(),
+            );
+        }
+        if self.fps.is_some_and(|fps| fps <= 0.0) {
+            return Err("The output frame rate must be above 0".into());
+        }
+        if self.mode != RenderMode::Encode && (self.scales() || self.fps.is_some()) {
+            return Err(
+                "The resolution and frame rate can't be changed when copying streams".into(),
+            );
+        }
+        let fades = self.fades;
+        if [
+            fades.audio_in,
+            fades.audio_out,
+            fades.video_in,
+            fades.video_out,
+        ]
+        .iter()
+        .any(|duration| !duration.is_finite() || *duration < 0.0)
+        {
+            return Err("Fade durations must be at least 0".into());
+        }
+        if fades.audio_in + fades.audio_out > self.duration()
+            || fades.video_in + fades.video_out > self.duration()
+        {
+            return Err("Fades can't be longer than the output".into());
+        }
+        // Smart cuts copy the video but still encode the audio
+        if self.mode == RenderMode::Copy && fades.has_audio() {
+            return Err("Audio can't be faded when copying streams".into());
+        }
+        if self.mode != RenderMode::Encode && fades.has_video() {
+            return Err("Video can't be faded when copying it".into());
+        }
+        if self
+            .subtitle_tracks
+            .iter()
+            .filter(|track| track.burn_in)
+            .count()
+            > 1
+        {
+            return Err("Only one subtitle track can be burned in".into());
+        }
+        if self.mode != RenderMode::Encode && self.burns_in_subtitles() {
+            return Err("Subtitles can't be burned in when copying the video".into());
+        }
+        // Subtitle streams can't be cut by filters, only by the concat demuxer of a stream copy
+        if self.mode != RenderMode::Copy
+            && self.trim_ranges().len() > 1
+            && self.subtitle_tracks.iter().any(|track| !track.burn_in)
+        {
+            return Err(
+                "Subtitle tracks can only be kept from several trim ranges when copying streams"
+                    .into(),
+            );
+        }
+        if self
+            .chapters
+            .iter()
+            .any(|chapter| chapter.start < 0.0 || chapter.end <= chapter.start)
+        {
+            return Err("Chapters must start at or after 0 and end after they start".into());
+        }
+        if self.mode != RenderMode::Encode && self.loudness.is_some() {
+            return Err("Loudness can't be normalised when copying streams".into());
+        }
+        if self.mode != RenderMode::Encode && self.two_pass {
+            return Err("Two-pass encoding can't be used when copying streams".into());
+        }
+        if self.two_pass && !TWO_PASS_ENCODERS.contains(&self.v_codec_id.as_str()) {
+            return Err(format!(
+                "Two-pass encoding is not supported by {}",
+                self.v_codec_id
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Stream copies can only start at keyframes, so move each range back to the one before it
+    async fn snap_to_keyframes(&mut self) -> Result<(), String> {
+        let ranges =
+            keyframes::snap_to_keyframes(self.input_filepath.clone(), self.trim_ranges()).await?;
+        match ranges.as_slice() {
+            [range] => {
+                self.trim_start = range.start;
+                self.trim_end = range.end;
+            }
+            _ => self.trim_ranges = ranges,
+        }
+        Ok(())
+    }
+
+    // The subtitles filter picks its track by the position among the subtitle streams, not the stream index
+    async fn find_burn_in_position(&mut self) -> Result<(), String> {
+        let Some(track) = self.subtitle_tracks.iter().find(|track| track.burn_in) else {
+            return Ok(());
+        };
+
+        let json = ffprobe_cmd::probe_entries(
+            &self.input_filepath,
+            &["-select_streams", "s"],
+            "stream=index,codec_name",
+        )
+        .await?;
+        let streams = json["streams"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let position = streams
+            .iter()
+            .position(|stream| stream["index"].as_u64() == Some(track.index.into()))
+            .ok_or_else(|| format!("Stream {} is not a subtitle track", track.index))?;
+
+        let codec = streams[position]["codec_name"].as_str().unwrap_or_default();
+        if BITMAP_SUBTITLE_CODECS.contains(&codec) {
+            return Err(format!(
+                "{codec} subtitles are images, which can't be burned in"
+            ));
+        }
+
+        self.burn_in_position = Some(position as u32);
+        Ok(())
+    }
+
+    // Hardware scalers can't keep the aspect ratio themselves, so the source size is needed to work it out.
+    // It's probed for any hardware encoder, since a size-limited render may only start scaling later
+    async fn find_source_size(&mut self) -> Result<(), String> {
+        if self.mode == RenderMode::Encode
+            && HardwareScaler::for_encoder(&self.v_codec_id).is_some()
+        {
+            self.source_size = Some(downscale::probe_video_size(&self.input_filepath).await?);
+        }
+        Ok(())
+    }
+
+    fn burns_in_subtitles(&self) -> bool {
+        self.subtitle_tracks.iter().any(|track| track.burn_in)
+    }
+
+    // Moves the chapters to where their trim ranges end up in the output, cutting them at the edges of the ranges.
+    // A chapter spanning the gap between two ranges is joined back together
+    fn output_chapters(&self) -> Vec<Chapter> {
+        let mut chapters: Vec<(usize, Chapter)> = Vec::new();
+        let mut offset = 0.0;
+
+        for range in self.trim_ranges() {
+            for (i, chapter) in self.chapters.iter().enumerate() {
+                let start = chapter.start.max(range.start);
+                let end = chapter.end.min(range.end);
+                if end <= start {
+                    continue;
+                }
+
+                let start = offset + start - range.start;
+                let end = offset + end - range.start;
+                match chapters.last_mut() {
+                    Some((last, output)) if *last == i && output.end == start => output.end = end,
+                    _ => chapters.push((
+                        i,
+                        Chapter {
+                            start,
+                            end,
+                            title: chapter.title.clone(),
+                        },
+                    )),
+                }
+            }
+            offset += range.end - range.start;
+        }
+
+        chapters.into_iter().map(|(_, chapter)| chapter).collect()
+    }
+
+    fn set_bitrates(&mut self, bitrates: Bitrates) {
+        self.target_bitrate = bitrates.target;
+        self.min_bitrate = bitrates.min;
+        self.max_bitrate = bitrates.max;
+    }
+
+    fn rate_control_args(&self) -> Vec<String> {
+        let replacements = [
+            ("{TARGET_BITRATE}", Some(self.target_bitrate)),
+            ("{MIN_BITRATE}", Some(self.min_bitrate)),
+            ("{MAX_BITRATE}", Some(self.max_bitrate)),
+            ("{CRF_VALUE}", self.crf_value),
+            ("{BUF_SIZE}", Some(self.buf_size)),
+        ];
+
+        self.codec_rate_control
+            .iter()
+            .map(|arg| {
+                let mut arg = arg.clone();
+                for (template, value) in replacements {
+                    if let Some(value) = value {
+                        arg = arg.replace(template, &value.to_string());
+                    }
+                }
+                arg
+            })
+            .collect()
+    }
+
+    fn pass_args(&self, pass: EncodePass, rate_control_args: &mut Vec<String>) {
+        let (number, log_name) = match pass {
+            EncodePass::First { log_name } => (1, log_name),
+            EncodePass::Second { log_name } => (2, log_name),
+        };
+
+        // libx265 ignores -pass, so its pass has to be given through its own parameters
+        if self.v_codec_id == "libx265" {
+            let x265_params = format!("pass={number}:stats={log_name}.log");
+
+            match rate_control_args
+                .iter()
+                .position(|arg| arg == "-x265-params")
+            {
+                Some(i) if i + 1 < rate_control_args.len() => {
+                    rate_control_args[i + 1].push(':');
+                    rate_control_args[i + 1].push_str(&x265_params);
+                }
+                _ => rate_control_args.extend(["-x265-params".to_owned(), x265_params]),
+            }
+        } else {
+            rate_control_args.extend([
+                "-pass".to_owned(),
+                number.to_string(),
+                "-passlogfile".to_owned(),
+                log_name.to_owned(),
+            ]);
+        }
+    }
+
+    // Ranges are relative to the seeked input when several of them are concatenated
+    fn concat_ranges(&self, seek: f64) -> Vec<(f64, f64)> {
+        self.trim_ranges()
+            .iter()
+            .map(|range| (range.start - seek, range.end - seek))
+            .collect()
+    }
+
+    fn scales(&self) -> bool {
+        self.width.is_some() || self.height.is_some()
+    }
+
+    // Hardware scaler to use, only when scaling with an encoder on the same GPU.
+    // Fades and burned in subtitles can't read frames in GPU memory, so they fall back to the software scaler
+    fn hardware_scaler(&self) -> Option<HardwareScaler> {
+        (self.scales()
+            && self.source_size.is_some()
+            && !self.fades.has_video()
+            && !self.burns_in_subtitles())
+        .then(|| HardwareScaler::for_encoder(&self.v_codec_id))
+        .flatten()
+    }
+
+    fn scale_filter(&self) -> Option<String> {
+        if !self.scales() {
+            return None;
+        }
+
+        let scaler = self.hardware_scaler();
+        let mut filter = match scaler.zip(self.source_size) {
+            // Hardware scalers don't all take -2 or force_original_aspect_ratio, so they're given the exact size
+            Some((scaler, source)) => {
+                let (width, height) = self.output_size(source);
+                format!("{}=w={width}:h={height}", scaler.filter())
+            }
+            None => {
+                // -2 keeps the aspect ratio while rounding to even
+                let even_arg = |size: u32| even(size).to_string();
+                let (width, height) = match (self.width, self.height) {
+                    (Some(width), None) if self.lock_ratio => (even_arg(width), "-2".to_owned()),
+                    (None, Some(height)) if self.lock_ratio => ("-2".to_owned(), even_arg(height)),
+                    (width, height) => (
+                        width.map_or_else(|| "trunc(iw/2)*2".to_owned(), even_arg),
+                        height.map_or_else(|| "trunc(ih/2)*2".to_owned(), even_arg),
+                    ),
+                };
+                let mut filter = format!("scale=w={width}:h={height}");
+                if self.lock_ratio && self.width.is_some() && self.height.is_some() {
+                    filter.push_str(":force_original_aspect_ratio=decrease:force_divisible_by=2");
+                }
+                filter
+            }
+        };
+
+        match scaler {
+            None => filter.push_str(&format!(":flags={}", self.scale_algorithm.flag())),
+            Some(HardwareScaler::Cuda) => {
+                if let Some(interp_algo) = self.scale_algorithm.cuda_interp_algo() {
+                    filter.push_str(&format!(":interp_algo={interp_algo}"));
+                }
+            }
+            Some(HardwareScaler::Qsv | HardwareScaler::Vaapi) => {}
+        }
+
+        Some(filter)
+    }
+
+    // Even output dimensions for the source size, fitting inside the width and height when the ratio is locked
+    fn output_size(&self, source: VideoSize) -> (u32, u32) {
+        let width_ratio = self.width.map(|width| width as f64 / source.width as f64);
+        let height_ratio = self
+            .height
+            .map(|height| height as f64 / source.height as f64);
+        let (width_ratio, height_ratio) = match (width_ratio, height_ratio) {
+            (Some(width_ratio), Some(height_ratio)) if self.lock_ratio => {
+                let ratio = width_ratio.min(height_ratio);
+                (ratio, ratio)
+            }
+            (Some(ratio), None) | (None, Some(ratio)) if self.lock_ratio => (ratio, ratio),
+            (width_ratio, height_ratio) => {
+                (width_ratio.unwrap_or(1.0), height_ratio.unwrap_or(1.0))
+            }
+        };
+        let scale = |size: u32, ratio: f64| even((size as f64 * ratio).round() as u32);
+        (
+            scale(source.width, width_ratio),
+            scale(source.height, height_ratio),
+        )
+    }
+
+    // Start of the earliest trim range and the time (s) from it to the end of the latest one
+    fn trim_span(&self) -> (f64, f64) {
+        let ranges = self.trim_ranges();
+        let start = ranges
+            .iter()
+            .map(|range| range.start)
+            .fold(f64::INFINITY, f64::min);
+        let end = ranges.iter().map(|range| range.end).fold(0.0, f64::max);
+        (start, end - start)
+    }
+
+    fn track_mix(&self, index: u32) -> Option<&AudioTrackMix> {
+        self.audio_mix.iter().find(|mix| mix.index == index)
+    }
+
+    // Audio streams to map into the output, with the loudness normalisation and fades applied.
+    // `timeline_start` is where the output starts in the timestamps the filters see
+    fn audio_outputs(
+        &self,
+        graph: &mut FilterGraph,
+        input: u32,
+        seek: Option<f64>,
+        timeline_start: f64,
+    ) -> Vec<AudioOutput> {
+        let mut outputs = self.mix_audio_tracks(graph, input, seek);
+
+        if let Some(target) = self.loudness {
+            for (output, measurement) in outputs.iter_mut().zip(&self.loudness_measurements) {
+                if let Some(filter) = target.normalize_filter(measurement) {
+                    let sample_rate = self.sample_rate.unwrap_or(loudness::DEFAULT_SAMPLE_RATE);
+                    output.stream = graph.then(
+                        &output.stream,
+                        &[filter, format!("aresample={sample_rate}")],
+                        "a",
+                    );
+                }
+            }
+        }
+
+        // Fade after normalising so the fades aren't evened out by it
+        for output in &mut outputs {
+            output.stream = graph.fade(
+                &output.stream,
+                MediaKind::Audio,
+                (timeline_start, self.duration()),
+                self.fades.audio_in,
+                self.fades.audio_out,
+            );
+        }
+
+        outputs
+    }
+
+    // Mixes the selected audio tracks of an input into one stream, or keeps them separate.
+    // The trim ranges (relative to `seek`) are joined when given one
+    fn mix_audio_tracks(
+        &self,
+        graph: &mut FilterGraph,
+        input: u32,
+        seek: Option<f64>,
+    ) -> Vec<AudioOutput> {
+        let inputs: Vec<MixInput> = self
+            .audio_tracks
+            .iter()
+            .map(|index| {
+                let mut stream = Stream::Input(format!("{input}:{index}"));
+                if let Some(seek) = seek {
+                    stream =
+                        graph.trim_concat(&stream, &self.concat_ranges(seek), MediaKind::Audio);
+                }
+
+                let mix = self.track_mix(*index);
+                MixInput {
+                    stream,
+                    gain: mix.map_or(1.0, |mix| mix.gain),
+                    pan: mix.and_then(|mix| mix.pan),
+                }
+            })
+            .collect();
+
+        if self.separate_audio_tracks {
+            let layout = self.channel_layout.as_deref();
+            return inputs
+                .into_iter()
+                .zip(&self.audio_tracks)
+                .map(|(input, index)| AudioOutput {
+                    stream: graph.mix(&[input], layout),
+                    track: Some(*index),
+                })
+                .collect();
+        }
+
+        let layout = match (&self.channel_layout, inputs.len()) {
+            (_, 0) => return Vec::new(),
+            (Some(layout), _) => Some(layout.as_str()),
+            (None, 1) => None,
+            (None, _) => Some("stereo"),
+        };
+        vec![AudioOutput {
+            stream: graph.mix(&inputs, layout),
+            track: None,
+        }]
+    }
+
+    // Builds a command measuring the loudness of one audio output, which loudnorm prints to stderr
+    fn build_loudness_command(&self, target: LoudnessTarget, output: usize) -> Command {
+        let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+        let concatenate = self.trim_ranges().len() > 1;
+        let (seek, span) = self.trim_span();
+
+        // Seek the input, since output options would only apply after the filter graph had measured from the start.
+        // A single range is the whole span, so only concatenated ranges are cut further in the graph
+        command.args([
+            "-ss",
+            seek.to_string().as_str(),
+            "-t",
+            span.to_string().as_str(),
+            "-i",
+            &self.input_filepath,
+        ]);
+
+        let mut graph = FilterGraph::default();
+        let outputs = self.mix_audio_tracks(&mut graph, 0, concatenate.then_some(seek));
+        let measured = graph.then(&outputs[output].stream, &[target.measure_filter()], "a");
+
+        command.args([
+            "-filter_complex",
+            &graph.to_string(),
+            "-map",
+            &measured.map_arg(),
+            "-progress",
+            "pipe:1",
+            "-f",
+            "null",
+            "-",
+        ]);
+        self.pipe_output(&mut command);
+        command
+    }
+
+    fn build_command(&self, pass: Option<EncodePass>) -> Command {
+        let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+        let first_pass = matches!(pass, Some(EncodePass::First { .. }));
+        let concatenate = self.trim_ranges().len() > 1;
+
+        if let Some(scaler) = self.hardware_scaler() {
+            // Decode straight to GPU memory for the hardware scaler to read from
+            command.args([
+                "-hwaccel",
+                scaler.hwaccel(),
+                "-hwaccel_output_format",
+                scaler.hwaccel(),
+            ]);
+        }
+
+        let (seek, span) = self.trim_span();
+        if concatenate {
+            // Seek the input to the earliest range so FFMPEG doesn't decode everything before it
+            command.args([
+                "-ss",
+                seek.to_string().as_str(),
+                "-t",
+                span.to_string().as_str(),
+            ]);
+        }
+
+        command.args(["-i", &self.input_filepath]);
+        // A single range is cut with output options, which move the chapters back by the trim start again
+        self.chapter_input(
+            &mut command,
+            if concatenate { 0.0 } else { self.trim_start },
+        );
+        command.args(["-c:v", &self.v_codec_id, "-c:a", &self.a_codec_id]);
+
+        if !concatenate {
+            command.args([
+                "-ss",
+                self.trim_start.to_string().as_str(),
+                "-t",
+                self.duration().to_string().as_str(),
+            ]);
+        }
+
+        let mut graph = FilterGraph::default();
+
+        // Filters take a single stream, while mapping 0:v as it is keeps every video stream
+        let filtered = concatenate || self.burn_in_position.is_some();
+        let mut video = Stream::Input(if filtered { "0:v:0" } else { "0:v" }.into());
+        if let Some(position) = self.burn_in_position {
+            // Drawn before cutting the ranges, while the video still has the input's timestamps
+            let offset = if concatenate { seek } else { 0.0 };
+            video = graph.subtitles(&video, &self.input_filepath, position, offset);
+        }
+        if concatenate {
+            video = graph.trim_concat(&video, &self.concat_ranges(seek), MediaKind::Video);
+        }
+        // Joined ranges start at 0, a single range is trimmed after the filters so keeps the input's timestamps
+        let timeline_start = if concatenate { 0.0 } else { self.trim_start };
+        video = graph.fade(
+            &video,
+            MediaKind::Video,
+            (timeline_start, self.duration()),
+            self.fades.video_in,
+            self.fades.video_out,
+        );
+        if let Some(scale_filter) = self.scale_filter() {
+            video = graph.then(&video, &[scale_filter], "v");
+        }
+
+        // The first pass only analyses the video
+        let audio = if first_pass {
+            Vec::new()
+        } else {
+            self.audio_outputs(&mut graph, 0, concatenate.then_some(seek), timeline_start)
+        };
+
+        if !graph.is_empty() {
+            command.args(["-filter_complex", &graph.to_string()]);
+        }
+
+        command.args(["-map", &video.map_arg()]);
+        self.map_audio(&mut command, 0, &audio);
+        if !first_pass {
+            self.map_subtitles(&mut command, 0);
+        }
+        self.map_chapters(&mut command, 1);
+
+        if let Some(fps) = self.fps {
+            command.args(["-r", &fps.to_string()]);
+        }
+
+        let mut rate_control_args = self.rate_control_args();
+        if let Some(pass) = pass {
+            self.pass_args(pass, &mut rate_control_args);
+
+            // Pass logs are named relative to the temp directory, since x265 can't take paths containing colons
+            command.current_dir(TEMP_PATH.get().unwrap());
+        }
+        command.args(rate_control_args);
+
+        command.args(["-progress", "pipe:1"]);
+
+        if first_pass {
+            command.args(["-an", "-f", "null", "-"]);
+            self.pipe_output(&mut command);
+        } else {
+            self.output_args(&mut command);
+        }
+
+        command
+    }
+
+    // Builds a command copying the streams of the trim ranges without re-encoding them,
+    // multiple ranges are read through the concat demuxer from `concat_list`
+    fn build_copy_command(&self, concat_list: Option<&Path>) -> Command {
+        let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+
+        match concat_list {
+            Some(concat_list) => {
+                command.args(["-f", "concat", "-safe", "0", "-i"]);
+                command.arg(concat_list);
+            }
+            None => {
+                // Seeking the input lands on the keyframe the start was snapped to
+                command.args([
+                    "-ss",
+                    self.trim_start.to_string().as_str(),
+                    "-i",
+                    &self.input_filepath,
+                ]);
+            }
+        }
+        self.chapter_input(&mut command, 0.0);
+        if concat_list.is_none() {
+            command.args(["-t", self.duration().to_string().as_str()]);
+        }
+
+        command.args(["-map", "0:v"]);
+        // Copied tracks can't be merged, so each one is kept as its own stream
+        for index in &self.audio_tracks {
+            command.args(["-map", &format!("0:{index}")]);
+        }
+
+        command.args([
+            "-c",
+            "copy",
+            "-avoid_negative_ts",
+            "make_zero",
+            "-progress",
+            "pipe:1",
+        ]);
+        // After -c copy, so subtitles the container can't hold are still converted
+        self.map_subtitles(&mut command, 0);
+        self.map_chapters(&mut command, 1);
+
+        self.output_args(&mut command);
+        command
+    }
+
+    // Builds a command writing the video of one smart cut segment to `output`
+    fn build_segment_command(
+        &self,
+        segment: Segment,
+        encoder: &SegmentEncoder,
+        output: &Path,
+    ) -> Command {
+        let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+
+        match segment {
+            Segment::Encode(range) => {
+                command.args([
+                    "-ss",
+                    range.start.to_string().as_str(),
+                    "-i",
+                    &self.input_filepath,
+                    "-t",
+                    (range.end - range.start).to_string().as_str(),
+                    "-map",
+                    "0:v:0",
+                ]);
+                command.args(&encoder.args);
+            }
+            Segment::Copy(range) => {
+                // Seek just past the keyframe so rounding can't land on the one before it,
+                // and stop just before the keyframe the next segment starts at
+                command.args([
+                    "-ss",
+                    (range.start + KEYFRAME_EPSILON).to_string().as_str(),
+                    "-i",
+                    &self.input_filepath,
+                    "-t",
+                    (range.end - range.start - 2.0 * KEYFRAME_EPSILON)
+                        .to_string()
+                        .as_str(),
+                    "-map",
+                    "0:v:0",
+                    "-c:v",
+                    "copy",
+                    "-avoid_negative_ts",
+                    "make_zero",
+                ]);
+            }
+        }
+
+        command.args(["-an", "-sn", "-dn", "-progress", "pipe:1", "-y"]);
+        command.arg(output);
+        self.pipe_output(&mut command);
+        command
+    }
+
+    // Builds a command joining the video segments of a smart cut listed in `concat_list`,
+    // with the audio encoded from the trim ranges of the input
+    fn build_smart_join_command(&self, concat_list: &Path) -> Command {
+        let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+        let concatenate = self.trim_ranges().len() > 1;
+        let (seek, span) = self.trim_span();
+
+        command.args(["-f", "concat", "-safe", "0", "-i"]);
+        command.arg(concat_list);
+        command.args([
+            "-ss",
+            seek.to_string().as_str(),
+            "-t",
+            span.to_string().as_str(),
+            "-i",
+            &self.input_filepath,
+        ]);
+        self.chapter_input(&mut command, 0.0);
+
+        let mut graph = FilterGraph::default();
+        // The input is seeked to the trim, so its timestamps start at 0
+        let audio = self.audio_outputs(&mut graph, 1, concatenate.then_some(seek), 0.0);
+        if !graph.is_empty() {
+            command.args(["-filter_complex", &graph.to_string()]);
+        }
+
+        command.args(["-map", "0:v", "-c:v", "copy", "-c:a", &self.a_codec_id]);
+        self.map_audio(&mut command, 1, &audio);
+        self.map_subtitles(&mut command, 1);
+        self.map_chapters(&mut command, 2);
+
+        command.args(["-progress", "pipe:1"]);
+        self.output_args(&mut command);
+        command
+    }
+
+    // Maps the audio outputs, tracks kept separate get their own codec, bitrate and the tags of the input stream
+    fn map_audio(&self, command: &mut Command, input: u32, outputs: &[AudioOutput]) {
+        if outputs.is_empty() {
+            return;
+        }
+
+        for (i, output) in outputs.iter().enumerate() {
+            command.args(["-map", &output.stream.map_arg()]);
+
+            let Some(index) = output.track else {
+                continue;
+            };
+            let mix = self.track_mix(index);
+            if let Some(codec_id) = mix.and_then(|mix| mix.codec_id.as_ref()) {
+                command.args([&format!("-c:a:{i}"), codec_id]);
+            }
+            if let Some(bitrate) = mix.and_then(|mix| mix.bitrate).or(self.audio_bitrate) {
+                command.args([&format!("-b:a:{i}"), &format!("{bitrate}k")]);
+            }
+            // Filtered streams lose their metadata, so copy the language and handler name tags over
+            command.args([
+                &format!("-map_metadata:s:a:{i}"),
+                &format!("{input}:s:{index}"),
+            ]);
+        }
+
+        if !self.separate_audio_tracks {
+            if let Some(audio_bitrate) = self.audio_bitrate {
+                command.args(["-b:a", &format!("{audio_bitrate}k")]);
+            }
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            command.args(["-ar", &sample_rate.to_string()]);
+        }
+        if let Some(channels) = self.audio_channels {
+            command.args(["-ac", &channels.to_string()]);
+        }
+    }
+
+    // Maps the subtitle tracks that aren't burned in, converting them for containers that only take one text format
+    fn map_subtitles(&self, command: &mut Command, input: u32) {
+        let tracks: Vec<_> = self
+            .subtitle_tracks
+            .iter()
+            .filter(|track| !track.burn_in)
+            .collect();
+        if tracks.is_empty() {
+            return;
+        }
+
+        for track in tracks {
+            command.args(["-map", &format!("{input}:{}", track.index)]);
+        }
+
+        let extension = Path::new(&self.output_filepath)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let codec = match extension.as_str() {
+            "mp4" | "m4v" | "mov" => "mov_text",
+            "webm" => "webvtt",
+            // Styled subtitles need the fonts attached to the input
+            "mkv" => {
+                command.args(["-map", &format!("{input}:t?")]);
+                "copy"
+            }
+            _ => "copy",
+        };
+        command.args(["-c:s", codec]);
+    }
 
-struct RenderTask {
-    canceller: tokio::sync::oneshot::Sender<()>,
+    // Adds the FFMETADATA file of the trimmed chapters as an input, after the media inputs
+    // `offset` (s) is added to the chapters, countering an output start time FFMPEG subtracts from them
+    fn chapter_input(&self, command: &mut Command, offset: f64) {
+        if let Some(chapter_list) = &self.chapter_list {
+            if offset > 0.0 {
+                command.args(["-itsoffset", offset.to_string().as_str()]);
+            }
+            command.args(["-f", "ffmetadata", "-i"]);
+            command.arg(chapter_list);
+        }
+    }
+
+    // Takes the chapters from the FFMETADATA input, or leaves them out since the input's wouldn't match the trim
+    fn map_chapters(&self, command: &mut Command, input: u32) {
+        let input = match self.chapter_list {
+            Some(_) => input.to_string(),
+            None => "-1".to_owned(),
+        };
+        command.args(["-map_chapters", &input]);
+    }
+
+    /// Bitrate (Kb/s) of all audio in the output, adding up the bitrates of tracks kept separate
+    fn output_audio_bitrate(&self) -> f64 {
+        if self.audio_tracks.is_empty() {
+            0.0
+        } else if self.separate_audio_tracks {
+            self.audio_tracks
+                .iter()
+                .filter_map(|index| {
+                    self.track_mix(*index)
+                        .and_then(|mix| mix.bitrate)
+                        .or(self.audio_bitrate)
+                })
+                .sum()
+        } else {
+            self.audio_bitrate.unwrap_or(0.0)
+        }
+    }
+
+    fn output_args(&self, command: &mut Command) {
+        if self.override_file {
+            command.arg("-y");
+        } else {
+            command.arg("-n");
+        }
+
+        command.arg(&self.output_filepath);
+        self.pipe_output(command);
+    }
+
+    fn pipe_output(&self, command: &mut Command) {
+        #[cfg(target_os = "windows")]
+        command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+    }
 }
 
-static RENDER_TASKS: LazyLock<Mutex<HashMap<u32, RenderTask>>> = LazyLock::new(Default::default);
-static NEXT_RENDER_TASK: AtomicU32 = AtomicU32::new(0);
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeLimit {
+    max_size: f64, // MB
+    max_attempts: u32,
+    retry_threshold: f64,
+    /// Lower the resolution and frame rate when the size limit leaves too few bits per pixel,
+    /// unless either was set explicitly
+    #[serde(default)]
+    auto_downscale: bool,
+}
 
-#[tauri::command]
-pub async fn start_render(
-    window: Window,
-    input_filepath: &str,
-    output_filepath: &str,
-    v_codec_id: &str,
-    a_codec_id: &str,
-    override_file: bool,
-    audio_tracks: Vec<u32>,
-    codec_rate_control: Vec<&str>,
-    trim_start: f64,
-    trim_end: f64,
-) -> Result<u32, String> {
-    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
-
-    command.args([
-        "-i",
-        input_filepath,
-        "-c:v",
-        v_codec_id,
-        "-c:a",
-        a_codec_id,
-        "-ss",
-        trim_start.to_string().as_str(),
-        "-t",
-        (trim_end - trim_start).to_string().as_str(),
-        "-map",
-        "0:v",
-    ]);
-
-    if audio_tracks.len() < 2 {
-        if audio_tracks.len() == 1 {
-            command.args(["-map", format!("0:{}", audio_tracks[0]).as_str()]);
+/// Video bitrates in Kb/s
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bitrates {
+    pub target: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BestAttempt {
+    size: f64, // Bytes
+    bitrates: Bitrates,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchStep {
+    Retry,
+    Done,
+}
+
+fn minmax(min: f64, number: f64, max: f64) -> f64 {
+    number.max(min).min(max)
+}
+
+/// Searches for the bitrate producing the largest file that still fits under a size limit,
+/// one render attempt at a time.
+#[derive(Clone, Debug)]
+pub struct SizeLimitSearch {
+    limit: SizeLimit,
+    bitrates: Bitrates,
+    /// Size of the audio (bytes), which stays the same whatever the video bitrate
+    audio_size: f64,
+    attempt: u32,
+    best: Option<BestAttempt>,
+    last_percent_diff: f64,
+    max_set_bitrate: f64,
+    min_set_bitrate: Option<f64>,
+}
+
+impl SizeLimitSearch {
+    /// Starts a search for a render of `duration` seconds with audio at `audio_bitrate` Kb/s
+    pub fn new(limit: SizeLimit, duration: f64, audio_bitrate: f64) -> Self {
+        // Convert max size (MB) to megabits (Mb), then divide by duration (s) to get Mb/s, then multiply by 1000 to get Kb/s.
+        // The audio takes its share of that first, leaving the rest to the video
+        let theoretical_constant_bitrate =
+            ((limit.max_size * 8.0) / duration) * 1000.0 - audio_bitrate;
+
+        Self {
+            limit,
+            bitrates: Bitrates {
+                target: theoretical_constant_bitrate,
+                min: 0.0,
+                max: theoretical_constant_bitrate,
+            },
+            // Convert Kb/s to bytes/s, then multiply by duration (s) to get bytes
+            audio_size: audio_bitrate * 1000.0 / 8.0 * duration,
+            attempt: 0,
+            best: None,
+            last_percent_diff: 0.1,
+            max_set_bitrate: f64::INFINITY,
+            min_set_bitrate: None,
         }
-    } else {
-        let mut audio_command = String::new().to_owned();
-        for i in audio_tracks.iter() {
-            if *i == 1 {
-                command.arg("-filter_complex");
+    }
+
+    pub fn bitrates(&self) -> Bitrates {
+        self.bitrates
+    }
+
+    /// Starts the next attempt, returning its number (starting at 1)
+    pub fn begin_attempt(&mut self) -> u32 {
+        self.attempt += 1;
+        self.attempt
+    }
+
+    /// Records the size of the file produced by the current attempt and adjusts the bitrates for the next one
+    pub fn record(&mut self, size: u64) -> SearchStep {
+        let size = size as f64;
+        let max_size_bytes = self.limit.max_size * 1e6; // Convert MB to bytes
+
+        // Set best attempt to current settings if:
+        // there is no best attempt yet
+        // the resultant file is less than the target size and if the current target bitrate is larger than the last best attempt bitrate or if last best attempt was larger than the target size
+        // the resultant file is larger than the target size and is less than the last best attempt's size
+        let is_best = match self.best {
+            None => true,
+            Some(best) => {
+                (size <= max_size_bytes
+                    && (self.bitrates.target > best.bitrates.target || best.size > max_size_bytes))
+                    || (size >= max_size_bytes && size < best.size)
             }
+        };
+        if is_best {
+            self.best = Some(BestAttempt {
+                size,
+                bitrates: self.bitrates,
+            });
+        }
 
-            if *i != audio_tracks.len() as u32 {
-                audio_command.push_str(&format!("[0:{}]", *i).to_owned());
-            } else {
-                audio_command.push_str(
-                    &format!("[0:{}]amerge=inputs={}[a]", *i, &audio_tracks.len()).to_owned(),
-                );
+        // The attempt after this one will be the last, so it should use the best settings found so far
+        let final_attempt = self.attempt == self.limit.max_attempts.saturating_sub(1);
+
+        if self.adjust(size, final_attempt) && self.attempt < self.limit.max_attempts {
+            SearchStep::Retry
+        } else {
+            SearchStep::Done
+        }
+    }
+
+    // Adjusts the current bitrates to attempt to reach the target size limit,
+    // returning whether or not adjusting was needed
+    fn adjust(&mut self, size: f64, final_attempt: bool) -> bool {
+        let target_size_bytes = self.limit.max_size * 1e6;
+        // > 0 : over size limit
+        // < 0 : under size limit
+        let percent_diff = size / target_size_bytes - 1.0;
+
+        // Stop adjusting if the file fits and is within the retry threshold of the limit
+        if percent_diff <= 0.0 && -percent_diff < self.limit.retry_threshold {
+            return false;
+        }
+
+        // Only the video bitrate is adjusted, so compare the size of the video against what's left of the limit for it
+        let percent_diff =
+            (size - self.audio_size) / (target_size_bytes - self.audio_size).max(1.0) - 1.0;
+
+        // Constrain max allowed bitrate if the resultant size is bigger than the target size
+        if size > target_size_bytes {
+            self.max_set_bitrate = self.max_set_bitrate.min(self.bitrates.max);
+        }
+        // Constrain min allowed bitrate if the resultant size is smaller than the target size
+        if size < target_size_bytes {
+            self.min_set_bitrate = Some(self.bitrates.target);
+        }
+
+        // Use square root curve to produce multiplier
+        let multiplier =
+            (42.0 * (self.last_percent_diff.abs() - percent_diff.abs()).abs()).sqrt() + 1.0;
 
-                command.arg(&audio_command);
-                command.args(["-map", "[a]"]);
-                command.args(["-ac", "2"]); // Stereo audio channels
+        if !final_attempt {
+            match self.min_set_bitrate {
+                // If max and min bitrate bounds are set, search between them
+                Some(min_set) if self.max_set_bitrate != f64::INFINITY => {
+                    let range = self.max_set_bitrate - min_set;
+                    self.bitrates.target = minmax(
+                        min_set,
+                        self.bitrates.target - range * percent_diff,
+                        self.max_set_bitrate,
+                    );
+                    self.bitrates.max = minmax(
+                        min_set,
+                        self.bitrates.max - range * percent_diff,
+                        self.max_set_bitrate,
+                    );
+                }
+                min_set => {
+                    let floor = min_set.filter(|min| *min > 0.0).unwrap_or(0.01);
+                    self.bitrates.target = minmax(
+                        floor,
+                        self.bitrates.target - self.bitrates.target * percent_diff * multiplier,
+                        self.max_set_bitrate,
+                    );
+                    self.bitrates.max = minmax(
+                        floor,
+                        self.bitrates.max - self.bitrates.max * percent_diff * multiplier,
+                        self.max_set_bitrate,
+                    );
+                }
             }
+            self.last_percent_diff = percent_diff;
+        } else if let Some(best) = self.best {
+            // Give up on adjusting as we have ran out of attempts, use the best found settings so far instead
+            self.bitrates = Bitrates {
+                target: best.bitrates.target,
+                min: if best.bitrates.min > 0.0 {
+                    best.bitrates.min
+                } else {
+                    0.01
+                },
+                max: if best.bitrates.max == f64::INFINITY {
+                    best.bitrates.target
+                } else {
+                    best.bitrates.max
+                },
+            };
+        }
+
+        true
+    }
+}
+
+/// Progress of a render task, parsed from FFMPEG's `-progress` output
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RenderProgress {
+    /// The trim ranges were snapped to keyframes for a stream copy, these are the ones rendered
+    TrimSnapped {
+        ranges: Vec<TrimRange>,
+    },
+    /// The size limit was too small for the source, so the video is scaled down to this step
+    #[serde(rename_all = "camelCase")]
+    Downscaled {
+        width: u32,
+        height: u32,
+        fps: f64,
+        bits_per_pixel: f64,
+    },
+    /// The loudness of an audio output (counted from 0) is being measured before rendering
+    MeasuringLoudness {
+        track: u32,
+    },
+    /// An FFMPEG process has started, `pass` is only set for two-pass renders
+    Started {
+        attempt: u32,
+        pass: Option<u8>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        out_time_us: Option<i64>,
+        fps: Option<f64>,
+        /// Multiple of real-time
+        speed: Option<f64>,
+        /// In Kb/s
+        bitrate: Option<f64>,
+        total_size: Option<u64>,
+        frame: Option<u64>,
+    },
+    /// A size-limited attempt has produced a file of `size` bytes
+    #[serde(rename_all = "camelCase")]
+    AttemptFinished {
+        attempt: u32,
+        target_bitrate: f64,
+        size: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Finished {
+        output_size: u64,
+        /// Loudness of each audio output before it was normalised, empty unless normalising
+        loudness: Vec<LoudnessMeasurement>,
+    },
+    Cancelled,
+    #[serde(rename_all = "camelCase")]
+    Failed {
+        exit_status: Option<i32>,
+        stderr_tail: String,
+    },
+}
+
+/// Collects the `key=value` lines FFMPEG writes with `-progress` into [`RenderProgress`] updates.
+/// A block of keys always ends with a `progress` key, whichever keys the FFMPEG version writes before it.
+#[derive(Default, Debug)]
+struct ProgressParser {
+    fields: HashMap<String, String>,
+}
+
+impl ProgressParser {
+    /// Feeds one line of output, returning the progress once its block is complete
+    fn feed(&mut self, line: &str) -> Option<RenderProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+
+        if key != "progress" {
+            self.fields.insert(key.to_owned(), value.to_owned());
+            return None;
         }
+
+        let progress = RenderProgress::Progress {
+            out_time_us: self.field("out_time_us", ""),
+            fps: self.field("fps", ""),
+            speed: self.field("speed", "x"),
+            bitrate: self.field("bitrate", "kbits/s"),
+            total_size: self.field("total_size", ""),
+            frame: self.field("frame", ""),
+        };
+        self.fields.clear();
+
+        Some(progress)
+    }
+
+    fn field<T: FromStr>(&self, key: &str, unit: &str) -> Option<T> {
+        parse_value(self.fields.get(key).map(String::as_str), unit)
     }
+}
+
+const STDERR_TAIL_LINES: usize = 20;
+
+fn stderr_tail(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Receives the progress of a render task, e.g. to forward it to the frontend
+pub(crate) type ProgressSink = Box<dyn Fn(&RenderProgress) + Send + Sync>;
 
-    command.args(codec_rate_control);
+/// Sends the progress of one render task to its sink, keeping its status up to date
+struct RenderReporter {
+    sink: ProgressSink,
+    task_id: u32,
+    status: Arc<std::sync::Mutex<RenderStatus>>,
+}
 
-    command.args(["-progress", "pipe:1"]);
+impl RenderReporter {
+    fn send(&self, progress: &RenderProgress) {
+        {
+            let mut status = self.status.lock().unwrap();
+            if let RenderProgress::Started { attempt, pass } = *progress {
+                status.attempt = attempt;
+                status.pass = pass;
+            }
+            status.progress = Some(progress.clone());
+        }
 
-    if override_file {
-        command.arg("-y");
+        (self.sink)(progress);
     }
+}
 
-    command.arg(output_filepath);
+enum RenderError {
+    Cancelled,
+    Failed {
+        exit_status: Option<i32>,
+        stderr_tail: String,
+    },
+}
+
+impl From<std::io::Error> for RenderError {
+    fn from(e: std::io::Error) -> Self {
+        RenderError::Failed {
+            exit_status: None,
+            stderr_tail: e.to_string(),
+        }
+    }
+}
 
-    #[cfg(target_os = "windows")]
-    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
-    command.stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+impl From<String> for RenderError {
+    fn from(message: String) -> Self {
+        RenderError::Failed {
+            exit_status: None,
+            stderr_tail: message,
+        }
+    }
+}
 
-    let id = NEXT_RENDER_TASK.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    let (canceller, mut rx) = tokio::sync::oneshot::channel();
+async fn register_render_task(
+    settings: &RenderSettings,
+    sink: ProgressSink,
+) -> (RenderReporter, oneshot::Receiver<()>, Arc<AtomicBool>) {
+    let id = NEXT_RENDER_TASK.fetch_add(1, Ordering::Relaxed);
+    let (canceller, rx) = oneshot::channel();
+    let accept_current = Arc::new(AtomicBool::new(false));
+    let status = Arc::new(std::sync::Mutex::new(RenderStatus {
+        id,
+        input_filepath: settings.input_filepath.clone(),
+        output_filepath: settings.output_filepath.clone(),
+        attempt: 0,
+        pass: None,
+        progress: None,
+    }));
     {
         let mut render_tasks = RENDER_TASKS.lock().await;
-        render_tasks.insert(id, RenderTask { canceller });
+        render_tasks.insert(
+            id,
+            RenderTask {
+                canceller,
+                accept_current: Arc::clone(&accept_current),
+                status: Arc::clone(&status),
+            },
+        );
     }
-    tokio::task::spawn(async move {
-        let result = async {
-            let mut stderr_buf = String::new();
-            let mut lines = String::new();
-
-            let mut child = command
-                .spawn()
-                .map_err(|e| e.to_string())?;
-
-            let mut reader = BufReader::new(child.stdout.take().unwrap());
-            let mut child_stderr = child.stderr.take().unwrap();
-            let stderr_future = child_stderr.read_to_string(&mut stderr_buf);
-
-            let main_future = async {
-                const PROGRESS_LINES: u8 = 12;
-                let mut current_line: u8 = 0;
-
-                loop {
-                    let read_line = reader.read_line(&mut lines);
-
-                    tokio::select! {
-                        result = read_line => {
-                            if result.map_err(|e| e.to_string())? == 0 {
-                                break;
-                            }
-
-                            current_line += 1;
-
-                            if current_line >= PROGRESS_LINES {
-                                if lines.contains("progress=end") {
-                                    continue;
-                                }
-                                window.emit("export_progress", &lines).unwrap();
-
-                                lines.clear();
-                                current_line = 0;
-                            }
-                        }
-                        _ = &mut rx => {
-                            window.emit("export_progress", format!("cancelled")).unwrap();
-
-                            return Err("Cancelled".into());
-                        }
+
+    let reporter = RenderReporter {
+        sink,
+        task_id: id,
+        status,
+    };
+    (reporter, rx, accept_current)
+}
+
+// Runs a single FFMPEG process to completion, reporting its progress
+async fn run_ffmpeg(
+    reporter: &RenderReporter,
+    command: Command,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Result<(), RenderError> {
+    run_ffmpeg_with_stderr(reporter, command, cancel).await?;
+    Ok(())
+}
+
+// Runs a single FFMPEG process to completion like run_ffmpeg, returning everything it wrote to stderr
+async fn run_ffmpeg_with_stderr(
+    reporter: &RenderReporter,
+    mut command: Command,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Result<String, RenderError> {
+    let mut stderr_buf = String::new();
+    let mut line = String::new();
+    let mut parser = ProgressParser::default();
+
+    let mut child = command.spawn()?;
+
+    let mut reader = BufReader::new(child.stdout.take().unwrap());
+    let mut child_stderr = child.stderr.take().unwrap();
+    let stderr_future = child_stderr.read_to_string(&mut stderr_buf);
+
+    let main_future = async {
+        loop {
+            let read_line = reader.read_line(&mut line);
+
+            tokio::select! {
+                result = read_line => {
+                    if result? == 0 {
+                        break;
+                    }
+
+                    if let Some(progress) = parser.feed(&line) {
+                        reporter.send(&progress);
                     }
+                    line.clear();
+                }
+                _ = &mut *cancel => {
+                    child.start_kill()?;
+                    return Err(RenderError::Cancelled);
                 }
+            }
+        }
 
-                child.wait().await.map_err(|e| e.to_string())
-            };
+        Ok(child.wait().await?)
+    };
+
+    let (result1, result2) = tokio::join!(stderr_future, main_future);
+    let status = result2?;
+    result1?;
+
+    if !status.success() {
+        return Err(RenderError::Failed {
+            exit_status: status.code(),
+            stderr_tail: stderr_tail(&stderr_buf),
+        });
+    }
+
+    Ok(stderr_buf)
+}
+
+// Runs every pass needed to produce the output file once
+async fn render_output(
+    reporter: &RenderReporter,
+    settings: &RenderSettings,
+    attempt: u32,
+    pass_log: Option<&mut PassLog>,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Result<(), RenderError> {
+    if settings.mode == RenderMode::Smart {
+        return render_smart_cut(reporter, settings, attempt, cancel).await;
+    }
+    if settings.mode == RenderMode::Copy {
+        let input = Path::new(&settings.input_filepath);
+        let concat_list = match settings.trim_ranges().as_slice() {
+            [_] => None,
+            ranges => {
+                let files: Vec<_> = ranges.iter().map(|range| (input, Some(*range))).collect();
+                Some(TempFile::write(
+                    format!("render-{}.ffconcat", reporter.task_id),
+                    concat_script(&files),
+                )?)
+            }
+        };
+        reporter.send(&RenderProgress::Started {
+            attempt,
+            pass: None,
+        });
+        return run_ffmpeg(
+            reporter,
+            settings.build_copy_command(concat_list.as_ref().map(|list| list.path.as_path())),
+            cancel,
+        )
+        .await;
+    }
+
+    let Some(pass_log) = pass_log else {
+        reporter.send(&RenderProgress::Started {
+            attempt,
+            pass: None,
+        });
+        return run_ffmpeg(reporter, settings.build_command(None), cancel).await;
+    };
+
+    // The first pass only gathers statistics about the video, so later attempts at other bitrates can reuse it
+    if !pass_log.analysed {
+        let first_pass = EncodePass::First {
+            log_name: &pass_log.name,
+        };
+        reporter.send(&RenderProgress::Started {
+            attempt,
+            pass: Some(1),
+        });
+        run_ffmpeg(reporter, settings.build_command(Some(first_pass)), cancel).await?;
+        pass_log.analysed = true;
+    }
+
+    let second_pass = EncodePass::Second {
+        log_name: &pass_log.name,
+    };
+    reporter.send(&RenderProgress::Started {
+        attempt,
+        pass: Some(2),
+    });
+    run_ffmpeg(reporter, settings.build_command(Some(second_pass)), cancel).await
+}
+
+// Renders the segments of a smart cut one at a time, then joins them with the audio
+async fn render_smart_cut(
+    reporter: &RenderReporter,
+    settings: &RenderSettings,
+    attempt: u32,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Result<(), RenderError> {
+    let encoder = smart_cut::matching_encoder(&settings.input_filepath).await?;
+    let segments =
+        smart_cut::plan_segments(&settings.input_filepath, &settings.trim_ranges()).await?;
+
+    reporter.send(&RenderProgress::Started {
+        attempt,
+        pass: None,
+    });
 
-            let (result1, result2) = tokio::join!(stderr_future, main_future);
-            result1.map_err(|e| e.to_string())?;
-            let status = result2?;
+    let mut segment_files = Vec::new();
+    for (i, segment) in segments.into_iter().enumerate() {
+        let segment_file = TempFile::new(format!(
+            "render-{}-{i}.{}",
+            reporter.task_id, encoder.extension
+        ));
+        let command = settings.build_segment_command(segment, &encoder, &segment_file.path);
+        segment_files.push(segment_file);
 
-            if !status.success() {
-                return Err(format!("{status}\n{stderr_buf}"));
+        run_ffmpeg(reporter, command, cancel).await?;
+    }
+
+    let files: Vec<_> = segment_files
+        .iter()
+        .map(|file| (file.path.as_path(), None))
+        .collect();
+    let concat_list = TempFile::write(
+        format!("render-{}.ffconcat", reporter.task_id),
+        concat_script(&files),
+    )?;
+    run_ffmpeg(
+        reporter,
+        settings.build_smart_join_command(&concat_list.path),
+        cancel,
+    )
+    .await
+}
+
+// Measures the loudness of every audio output, so the render can normalise it
+async fn measure_loudness(
+    reporter: &RenderReporter,
+    settings: &mut RenderSettings,
+    target: LoudnessTarget,
+    cancel: &mut oneshot::Receiver<()>,
+) -> Result<(), RenderError> {
+    let output_count = if settings.separate_audio_tracks {
+        settings.audio_tracks.len()
+    } else {
+        settings.audio_tracks.len().min(1)
+    };
+
+    for output in 0..output_count {
+        reporter.send(&RenderProgress::MeasuringLoudness {
+            track: output as u32,
+        });
+        let stderr = run_ffmpeg_with_stderr(
+            reporter,
+            settings.build_loudness_command(target, output),
+            cancel,
+        )
+        .await?;
+        settings
+            .loudness_measurements
+            .push(loudness::parse_measurement(&stderr)?);
+    }
+
+    Ok(())
+}
+
+fn report_render_result(
+    reporter: &RenderReporter,
+    settings: &RenderSettings,
+    result: Result<(), RenderError>,
+) {
+    let progress = match result.and_then(|()| Ok(std::fs::metadata(&settings.output_filepath)?)) {
+        Ok(metadata) => RenderProgress::Finished {
+            output_size: metadata.len(),
+            loudness: settings.loudness_measurements.clone(),
+        },
+        Err(e) => {
+            // Don't leave a partial file behind
+            let _ = std::fs::remove_file(&settings.output_filepath);
+
+            match e {
+                RenderError::Cancelled => RenderProgress::Cancelled,
+                RenderError::Failed {
+                    exit_status,
+                    stderr_tail,
+                } => RenderProgress::Failed {
+                    exit_status,
+                    stderr_tail,
+                },
             }
+        }
+    };
+
+    reporter.send(&progress);
+}
+
+// Scales the video down the ladder if the bitrate (Kb/s) is too low for the source resolution and frame rate
+async fn downscale_for_bitrate(
+    reporter: &RenderReporter,
+    settings: &mut RenderSettings,
+    bitrate: f64,
+) -> Result<(), RenderError> {
+    let source = downscale::probe_video_size(&settings.input_filepath).await?;
+    let Some(step) = downscale::pick_step(source, bitrate) else {
+        return Ok(());
+    };
+
+    if step.height < source.height {
+        settings.width = None;
+        settings.height = Some(step.height);
+        settings.lock_ratio = true;
+    }
+    if step.fps < source.fps {
+        settings.fps = Some(step.fps);
+    }
+
+    reporter.send(&RenderProgress::Downscaled {
+        width: step.width,
+        height: step.height,
+        fps: step.fps,
+        bits_per_pixel: step.bits_per_pixel,
+    });
+    Ok(())
+}
+
+// Renders repeatedly, adjusting the bitrate until the output fits under the size limit
+async fn run_size_limited_render(
+    reporter: &RenderReporter,
+    settings: &mut RenderSettings,
+    size_limit: SizeLimit,
+    cancel: &mut oneshot::Receiver<()>,
+    accept_current: &AtomicBool,
+) -> Result<(), RenderError> {
+    let mut search = SizeLimitSearch::new(
+        size_limit,
+        settings.duration(),
+        settings.output_audio_bitrate(),
+    );
+    if size_limit.auto_downscale && !settings.scales() && settings.fps.is_none() {
+        downscale_for_bitrate(reporter, settings, search.bitrates().target).await?;
+    }
+    let mut pass_log = settings.two_pass.then(|| PassLog::new(reporter.task_id));
+
+    loop {
+        let attempt = search.begin_attempt();
+        settings.set_bitrates(search.bitrates());
 
-            window.emit("export_progress", "progress=end").unwrap();
+        render_output(reporter, settings, attempt, pass_log.as_mut(), cancel).await?;
 
-            Ok::<_, String>(())
+        let size = std::fs::metadata(&settings.output_filepath)?.len();
+
+        reporter.send(&RenderProgress::AttemptFinished {
+            attempt,
+            target_bitrate: settings.target_bitrate,
+            size,
+        });
+
+        if accept_current.load(Ordering::Relaxed) || search.record(size) == SearchStep::Done {
+            return Ok(());
         }
-        .await;
 
-        RENDER_TASKS.lock().await.remove(&id);
+        std::fs::remove_file(&settings.output_filepath)?;
+    }
+}
+
+/// Starts rendering in the background, returning the ID of the render task
+pub(crate) async fn spawn_render(
+    mut settings: RenderSettings,
+    size_limit: Option<SizeLimit>,
+    sink: ProgressSink,
+) -> Result<u32, String> {
+    settings.validate()?;
+    if size_limit.is_some() && settings.duration() <= 0.0 {
+        return Err("Cannot limit the size of an empty render".into());
+    }
+    if size_limit.is_some() && settings.mode != RenderMode::Encode {
+        return Err("Cannot limit the size of a render that copies streams".into());
+    }
+    if let Some(size_limit) = size_limit {
+        // The audio bitrate has to be known to leave room for it in the size limit
+        if !settings.audio_tracks.is_empty() {
+            settings.audio_bitrate.get_or_insert(DEFAULT_AUDIO_BITRATE);
+        }
+
+        let search = SizeLimitSearch::new(
+            size_limit,
+            settings.duration(),
+            settings.output_audio_bitrate(),
+        );
+        if search.bitrates().target <= 0.0 {
+            return Err("The audio alone is larger than the size limit".into());
+        }
+    }
+    if settings.mode == RenderMode::Copy {
+        settings.snap_to_keyframes().await?;
+    }
+    settings.find_burn_in_position().await?;
+    settings.find_source_size().await?;
+
+    let (reporter, mut rx, accept_current) = register_render_task(&settings, sink).await;
+    let id = reporter.task_id;
+    tokio::task::spawn(async move {
+        if settings.mode == RenderMode::Copy {
+            reporter.send(&RenderProgress::TrimSnapped {
+                ranges: settings.trim_ranges(),
+            });
+        }
+
+        let result = async {
+            // Every command of the render reads the chapters from the same file
+            let _chapter_list = if settings.chapters.is_empty() {
+                None
+            } else {
+                let chapter_list = TempFile::write(
+                    format!("render-{id}.ffmetadata"),
+                    chapter_metadata(&settings.output_chapters()),
+                )?;
+                settings.chapter_list = Some(chapter_list.path.clone());
+                Some(chapter_list)
+            };
+
+            if let Some(target) = settings.loudness {
+                measure_loudness(&reporter, &mut settings, target, &mut rx).await?;
+            }
 
-        match result {
-            Ok(()) => {}
-            Err(e) => {
-                window
-                    .emit("export_progress", format!("error:{e}"))
-                    .unwrap();
+            match size_limit {
+                Some(size_limit) => {
+                    run_size_limited_render(
+                        &reporter,
+                        &mut settings,
+                        size_limit,
+                        &mut rx,
+                        &accept_current,
+                    )
+                    .await
+                }
+                None => {
+                    let mut pass_log = settings.two_pass.then(|| PassLog::new(id));
+                    render_output(&reporter, &settings, 1, pass_log.as_mut(), &mut rx).await
+                }
             }
         }
+        .await;
+
+        RENDER_TASKS.lock().await.remove(&id);
+
+        report_render_result(&reporter, &settings, result);
     });
 
     Ok(id)
 }
 
+fn channel_sink(channel: Channel) -> ProgressSink {
+    Box::new(move |progress| {
+        // The frontend may have gone away mid-render, which must not stop the render itself
+        let _ = channel.send(progress);
+    })
+}
+
+#[tauri::command]
+pub async fn start_render(settings: RenderSettings, on_progress: Channel) -> Result<u32, String> {
+    spawn_render(settings, None, channel_sink(on_progress)).await
+}
+
+#[tauri::command]
+pub async fn start_size_limited_render(
+    settings: RenderSettings,
+    size_limit: SizeLimit,
+    on_progress: Channel,
+) -> Result<u32, String> {
+    spawn_render(settings, Some(size_limit), channel_sink(on_progress)).await
+}
+
+#[tauri::command]
+pub async fn list_renders() -> Vec<RenderStatus> {
+    let mut renders: Vec<RenderStatus> = RENDER_TASKS
+        .lock()
+        .await
+        .values()
+        .map(|render_task| render_task.status.lock().unwrap().clone())
+        .collect();
+    renders.sort_by_key(|render| render.id);
+    renders
+}
+
+#[tauri::command]
+pub async fn set_accept_current_attempt(task_id: u32, accept: bool) -> Result<bool, bool> {
+    match RENDER_TASKS.lock().await.get(&task_id) {
+        Some(render_task) => {
+            render_task.accept_current.store(accept, Ordering::Relaxed);
+            Ok(true)
+        }
+        None => Err(false),
+    }
+}
+
 #[tauri::command]
 pub async fn cancel_render(task_id: u32) -> Result<bool, bool> {
     match RENDER_TASKS.lock().await.remove(&task_id) {
@@ -184,3 +2165,275 @@ pub async fn cancel_render(task_id: u32) -> Result<bool, bool> {
         None => Err(false),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds every line of the output, returning the progress of each completed block
+    fn parse(parser: &mut ProgressParser, output: &str) -> Vec<RenderProgress> {
+        output
+            .lines()
+            .filter_map(|line| parser.feed(line))
+            .collect()
+    }
+
+    fn progress(
+        out_time_us: Option<i64>,
+        speed: Option<f64>,
+        frame: Option<u64>,
+    ) -> RenderProgress {
+        RenderProgress::Progress {
+            out_time_us,
+            fps: None,
+            speed,
+            bitrate: None,
+            total_size: None,
+            frame,
+        }
+    }
+
+    #[test]
+    fn progress_parser_reads_any_keys_before_progress() {
+        let mut parser = ProgressParser::default();
+        let updates = parse(
+            &mut parser,
+            "frame=120\nfps=59.94\nstream_0_0_q=28.0\nbitrate=1234.5kbits/s\ntotal_size=524288\n\
+             out_time_us=2000000\nout_time=00:00:02.000000\ndup_frames=0\nspeed=1.98x\nprogress=continue\n",
+        );
+
+        assert_eq!(
+            updates,
+            [RenderProgress::Progress {
+                out_time_us: Some(2_000_000),
+                fps: Some(59.94),
+                speed: Some(1.98),
+                bitrate: Some(1234.5),
+                total_size: Some(524_288),
+                frame: Some(120),
+            }]
+        );
+    }
+
+    #[test]
+    fn progress_parser_leaves_missing_and_unknown_values_out() {
+        let mut parser = ProgressParser::default();
+        let updates = parse(
+            &mut parser,
+            "bitrate=N/A\nout_time_us=N/A\nspeed=N/A\nprogress=continue\n",
+        );
+
+        assert_eq!(updates, [progress(None, None, None)]);
+    }
+
+    #[test]
+    fn progress_parser_joins_blocks_split_across_reads() {
+        let mut parser = ProgressParser::default();
+
+        assert!(parse(&mut parser, "frame=10\r\nout_time_us=500000\r\n").is_empty());
+        assert_eq!(
+            parse(&mut parser, "speed=2x\r\nprogress=continue\r\nframe=20\r\n"),
+            [progress(Some(500_000), Some(2.0), Some(10))]
+        );
+        // Keys of the finished block don't carry over into the next one
+        assert_eq!(
+            parse(&mut parser, "progress=continue\n"),
+            [progress(None, None, Some(20))]
+        );
+    }
+
+    #[test]
+    fn progress_parser_reports_the_final_block() {
+        let mut parser = ProgressParser::default();
+        let updates = parse(
+            &mut parser,
+            "frame=300\nout_time_us=10000000\nspeed=3x\nprogress=end\n",
+        );
+
+        assert_eq!(updates, [progress(Some(10_000_000), Some(3.0), Some(300))]);
+    }
+
+    // 10 MB over 80 s leaves 1000 Kb/s for the video
+    fn search(max_attempts: u32) -> SizeLimitSearch {
+        let limit = SizeLimit {
+            max_size: 10.0,
+            max_attempts,
+            retry_threshold: 0.1,
+            auto_downscale: false,
+        };
+        SizeLimitSearch::new(limit, 80.0, 0.0)
+    }
+
+    #[test]
+    fn size_limit_search_stops_within_the_retry_threshold() {
+        let mut search = search(5);
+        search.begin_attempt();
+
+        assert_eq!(search.bitrates().target, 1000.0);
+        assert_eq!(search.record(9_500_000), SearchStep::Done);
+    }
+
+    #[test]
+    fn size_limit_search_narrows_the_bounds_around_the_limit() {
+        let mut search = search(5);
+
+        search.begin_attempt();
+        assert_eq!(search.record(12_000_000), SearchStep::Retry);
+        let lowered = search.bitrates();
+        assert!(lowered.target < 1000.0);
+        assert!(lowered.max <= 1000.0);
+
+        // Undershooting next searches between the bitrate that undershot and the one that overshot
+        search.begin_attempt();
+        assert_eq!(search.record(8_000_000), SearchStep::Retry);
+        let raised = search.bitrates();
+        assert!(raised.target > lowered.target && raised.target <= 1000.0);
+    }
+
+    #[test]
+    fn size_limit_search_stops_at_the_max_attempts() {
+        let mut search = search(2);
+
+        search.begin_attempt();
+        assert_eq!(search.record(12_000_000), SearchStep::Retry);
+        search.begin_attempt();
+        assert_eq!(search.record(12_000_000), SearchStep::Done);
+    }
+
+    #[test]
+    fn size_limit_search_falls_back_to_the_best_attempt_under_the_limit() {
+        let mut search = search(3);
+
+        search.begin_attempt();
+        assert_eq!(search.record(8_000_000), SearchStep::Retry);
+        assert!(search.bitrates().target > 1000.0);
+
+        // Overshooting before the last attempt goes back to the bitrates of the file that fit
+        search.begin_attempt();
+        assert_eq!(search.record(12_000_000), SearchStep::Retry);
+        assert_eq!(
+            search.bitrates(),
+            Bitrates {
+                target: 1000.0,
+                min: 0.01,
+                max: 1000.0,
+            }
+        );
+    }
+
+    // Settings with the required fields, overridden by the given ones
+    fn settings(fields: serde_json::Value) -> RenderSettings {
+        let mut json = serde_json::json!({
+            "inputFilepath": "input.mp4",
+            "outputFilepath": "output.mp4",
+            "vCodecId": "libx264",
+            "aCodecId": "aac",
+            "overrideFile": true,
+            "audioTracks": [1],
+            "codecRateControl": [],
+            "targetBitrate": 0.0,
+            "minBitrate": 0.0,
+            "maxBitrate": 0.0,
+            "bufSize": 0.0,
+            "crfValue": null,
+            "trimStart": 0.0,
+            "trimEnd": 10.0,
+        });
+        json.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(json).unwrap()
+    }
+
+    const SOURCE_1080P: VideoSize = VideoSize {
+        width: 1920,
+        height: 1080,
+        fps: 30.0,
+    };
+
+    #[test]
+    fn hardware_scaler_is_given_the_fitted_size() {
+        let mut settings = settings(serde_json::json!({
+            "vCodecId": "h264_qsv",
+            "width": 1280,
+            "height": 1280,
+        }));
+        settings.source_size = Some(SOURCE_1080P);
+
+        assert_eq!(
+            settings.scale_filter().as_deref(),
+            Some("scale_qsv=w=1280:h=720")
+        );
+    }
+
+    #[test]
+    fn hardware_scaler_rounds_the_locked_dimension_to_even() {
+        let mut settings = settings(serde_json::json!({
+            "vCodecId": "h264_nvenc",
+            "width": 853,
+            "scaleAlgorithm": "bicubic",
+        }));
+        settings.source_size = Some(SOURCE_1080P);
+
+        assert_eq!(
+            settings.scale_filter().as_deref(),
+            Some("scale_cuda=w=852:h=480:interp_algo=bicubic")
+        );
+    }
+
+    #[test]
+    fn software_scaler_keeps_the_aspect_ratio_itself() {
+        let settings = settings(serde_json::json!({ "width": 1280, "height": 1280 }));
+
+        assert_eq!(
+            settings.scale_filter().as_deref(),
+            Some("scale=w=1280:h=1280:force_original_aspect_ratio=decrease:force_divisible_by=2:flags=bicubic")
+        );
+    }
+
+    #[test]
+    fn loudness_command_seeks_the_input_of_a_single_range() {
+        let _ = FFMPEG_PATH.set("ffmpeg".into());
+        let settings = settings(serde_json::json!({ "trimStart": 5.0, "trimEnd": 15.0 }));
+        let target = serde_json::from_value(serde_json::json!({
+            "integrated": -16.0,
+            "truePeak": -1.5,
+        }))
+        .unwrap();
+        let command = settings.build_loudness_command(target, 0);
+        let args: Vec<_> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+
+        let input = args.iter().position(|arg| *arg == "-i").unwrap();
+        assert_eq!(args[..input], ["-ss", "5", "-t", "10"]);
+        assert!(!args[input..].contains(&"-ss"));
+    }
+
+    #[test]
+    fn single_range_offsets_the_chapters_by_the_trim_start() {
+        let _ = FFMPEG_PATH.set("ffmpeg".into());
+        let mut settings = settings(serde_json::json!({ "trimStart": 5.0, "trimEnd": 15.0 }));
+        settings.chapter_list = Some(PathBuf::from("render-0.ffmetadata"));
+        let command = settings.build_command(None);
+        let args: Vec<_> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+
+        let chapter_input = args.iter().position(|arg| *arg == "ffmetadata").unwrap();
+        assert_eq!(
+            args[chapter_input - 3..chapter_input],
+            ["-itsoffset", "5", "-f"]
+        );
+        assert_eq!(
+            args[chapter_input + 1..chapter_input + 3],
+            ["-i", "render-0.ffmetadata"]
+        );
+        let map_chapters = args.iter().position(|arg| *arg == "-map_chapters").unwrap();
+        assert_eq!(args[map_chapters + 1], "1");
+    }
+}
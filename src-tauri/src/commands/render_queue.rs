@@ -0,0 +1,368 @@
+use std::{
+    path::PathBuf,
+    sync::{LazyLock, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::sync::Mutex;
+
+use crate::LOCAL_DATA_PATH;
+
+use super::render::{self, ProgressSink, RenderProgress, RenderSettings, SizeLimit};
+
+const QUEUE_FILENAME: &str = "render_queue.json";
+
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+static RENDER_QUEUE: LazyLock<Mutex<RenderQueue>> = LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    Paused,
+    /// Picked to render, while its render task is being spawned
+    Starting,
+    #[serde(rename_all = "camelCase")]
+    Rendering {
+        task_id: u32,
+    },
+    #[serde(rename_all = "camelCase")]
+    Finished {
+        output_size: u64,
+    },
+    Failed {
+        message: String,
+    },
+    Cancelled,
+}
+
+impl JobState {
+    fn is_active(&self) -> bool {
+        matches!(self, JobState::Starting | JobState::Rendering { .. })
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(
+            self,
+            JobState::Finished { .. } | JobState::Failed { .. } | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueJob {
+    id: u32,
+    settings: RenderSettings,
+    size_limit: Option<SizeLimit>,
+    state: JobState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderQueue {
+    jobs: Vec<QueueJob>,
+    /// Maximum number of jobs rendering at once, jobs using hardware encoders never render alongside each other
+    concurrency: usize,
+    paused: bool,
+    next_job_id: u32,
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self {
+            jobs: Vec::new(),
+            concurrency: 1,
+            paused: false,
+            next_job_id: 0,
+        }
+    }
+}
+
+impl RenderQueue {
+    fn job_mut(&mut self, job_id: u32) -> Result<&mut QueueJob, String> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == job_id)
+            .ok_or_else(|| format!("No job with ID {job_id} in the render queue"))
+    }
+
+    // Picks the next job allowed to start, if any
+    fn next_startable_job(&mut self) -> Option<&mut QueueJob> {
+        let rendering = self.jobs.iter().filter(|job| job.state.is_active());
+        let rendering_count = rendering.clone().count();
+        let hardware_busy = rendering
+            .clone()
+            .any(|job| job.settings.uses_hardware_encoder());
+
+        if self.paused || rendering_count >= self.concurrency {
+            return None;
+        }
+
+        self.jobs.iter_mut().find(|job| {
+            matches!(job.state, JobState::Queued)
+                && !(hardware_busy && job.settings.uses_hardware_encoder())
+        })
+    }
+}
+
+fn queue_path() -> PathBuf {
+    LOCAL_DATA_PATH.get().unwrap().join(QUEUE_FILENAME)
+}
+
+fn save_queue(queue: &RenderQueue) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(queue).map_err(|e| e.to_string())?;
+
+    // Write to a temporary file first so a crash mid-write can't corrupt the saved queue
+    let path = queue_path();
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, json).map_err(|e| e.to_string())?;
+    std::fs::rename(&temp_path, &path).map_err(|e| e.to_string())
+}
+
+fn emit_queue(queue: &RenderQueue) {
+    if let Some(app) = APP_HANDLE.get() {
+        app.emit("render_queue", queue).unwrap();
+    }
+}
+
+/// Loads the queue saved by a previous run of the app and resumes it
+pub fn restore_queue(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+
+    let path = queue_path();
+    let mut queue = match std::fs::read(&path) {
+        Ok(json) => serde_json::from_slice::<RenderQueue>(&json).unwrap_or_else(|e| {
+            // Keep the unreadable queue aside, since saving the new one would overwrite it
+            let backup_path = path.with_extension("json.corrupt");
+            eprintln!(
+                "Failed to read the render queue, moving it to {}: {e}",
+                backup_path.display()
+            );
+            let _ = std::fs::rename(&path, &backup_path);
+            RenderQueue::default()
+        }),
+        Err(_) => RenderQueue::default(),
+    };
+
+    // Jobs that were rendering when the app closed left a partial file behind, so start them over
+    for job in queue.jobs.iter_mut() {
+        if job.state.is_active() {
+            let _ = std::fs::remove_file(job.settings.output_filepath());
+            job.state = JobState::Queued;
+        }
+    }
+
+    tauri::async_runtime::spawn(async move {
+        *RENDER_QUEUE.lock().await = queue;
+        start_jobs().await;
+    });
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct QueueProgressEvent<'a> {
+    job_id: u32,
+    progress: &'a RenderProgress,
+}
+
+fn job_sink(job_id: u32) -> ProgressSink {
+    Box::new(move |progress| {
+        let app = APP_HANDLE.get().unwrap();
+        app.emit(
+            "render_queue_progress",
+            QueueProgressEvent { job_id, progress },
+        )
+        .unwrap();
+
+        let state = match progress {
+            RenderProgress::Finished { output_size, .. } => JobState::Finished {
+                output_size: *output_size,
+            },
+            RenderProgress::Failed {
+                exit_status,
+                stderr_tail,
+            } => JobState::Failed {
+                message: match exit_status {
+                    Some(code) => format!("FFMPEG exited with {code}\n{stderr_tail}"),
+                    None => stderr_tail.clone(),
+                },
+            },
+            RenderProgress::Cancelled => JobState::Cancelled,
+            _ => return,
+        };
+        tokio::task::spawn(finish_job(job_id, state));
+    })
+}
+
+async fn finish_job(job_id: u32, state: JobState) {
+    {
+        let mut queue = RENDER_QUEUE.lock().await;
+        // The job may have been removed from the queue, cancelling it
+        if let Ok(job) = queue.job_mut(job_id) {
+            job.state = state;
+        }
+    }
+
+    start_jobs().await;
+}
+
+// Starts as many queued jobs as the concurrency allows
+async fn start_jobs() {
+    // Claim the jobs first, then spawn them without holding the queue, since spawning probes the input
+    let jobs: Vec<(u32, RenderSettings, Option<SizeLimit>)> = {
+        let mut queue = RENDER_QUEUE.lock().await;
+        let mut jobs = Vec::new();
+        while let Some(job) = queue.next_startable_job() {
+            job.state = JobState::Starting;
+            jobs.push((job.id, job.settings.clone(), job.size_limit));
+        }
+        jobs
+    };
+
+    let mut started = Vec::new();
+    for (job_id, settings, size_limit) in jobs {
+        let state = match render::spawn_render(settings, size_limit, job_sink(job_id)).await {
+            Ok(task_id) => JobState::Rendering { task_id },
+            Err(message) => JobState::Failed { message },
+        };
+        started.push((job_id, state));
+    }
+
+    let mut removed_tasks = Vec::new();
+    {
+        let mut queue = RENDER_QUEUE.lock().await;
+        for (job_id, state) in started {
+            match queue.job_mut(job_id) {
+                // A render that already ended has set its final state
+                Ok(job) if matches!(job.state, JobState::Starting) => job.state = state,
+                Ok(_) => {}
+                // Removed while starting, so its render has to be stopped here
+                Err(_) => {
+                    if let JobState::Rendering { task_id } = state {
+                        removed_tasks.push(task_id);
+                    }
+                }
+            }
+        }
+
+        let _ = save_queue(&queue);
+        emit_queue(&queue);
+    }
+
+    for task_id in removed_tasks {
+        let _ = render::cancel_render(task_id).await;
+    }
+}
+
+// Applies a change to the queue, then saves it and starts any jobs it allows
+async fn update_queue<T>(
+    update: impl FnOnce(&mut RenderQueue) -> Result<T, String>,
+) -> Result<T, String> {
+    let result = update(&mut *RENDER_QUEUE.lock().await)?;
+    start_jobs().await;
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn get_render_queue() -> RenderQueue {
+    RENDER_QUEUE.lock().await.clone()
+}
+
+#[tauri::command]
+pub async fn enqueue_render(
+    settings: RenderSettings,
+    size_limit: Option<SizeLimit>,
+) -> Result<u32, String> {
+    update_queue(|queue| {
+        let id = queue.next_job_id;
+        queue.next_job_id += 1;
+        queue.jobs.push(QueueJob {
+            id,
+            settings,
+            size_limit,
+            state: JobState::Queued,
+        });
+        Ok(id)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn remove_queue_job(job_id: u32) -> Result<(), String> {
+    let job = {
+        let mut queue = RENDER_QUEUE.lock().await;
+        let index = queue
+            .jobs
+            .iter()
+            .position(|job| job.id == job_id)
+            .ok_or_else(|| format!("No job with ID {job_id} in the render queue"))?;
+        queue.jobs.remove(index)
+    };
+
+    // Stop the render before starting others in its place
+    if let JobState::Rendering { task_id } = job.state {
+        let _ = render::cancel_render(task_id).await;
+    }
+
+    start_jobs().await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn move_queue_job(job_id: u32, index: usize) -> Result<(), String> {
+    update_queue(|queue| {
+        let from = queue
+            .jobs
+            .iter()
+            .position(|job| job.id == job_id)
+            .ok_or_else(|| format!("No job with ID {job_id} in the render queue"))?;
+        let job = queue.jobs.remove(from);
+        queue.jobs.insert(index.min(queue.jobs.len()), job);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn set_queue_job_paused(job_id: u32, paused: bool) -> Result<(), String> {
+    update_queue(|queue| {
+        let job = queue.job_mut(job_id)?;
+        job.state = match (&job.state, paused) {
+            (JobState::Queued, true) => JobState::Paused,
+            (JobState::Paused, false) => JobState::Queued,
+            (JobState::Queued, false) | (JobState::Paused, true) => return Ok(()),
+            _ => return Err("Only jobs waiting to render can be paused".into()),
+        };
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn set_queue_paused(paused: bool) -> Result<(), String> {
+    update_queue(|queue| {
+        queue.paused = paused;
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn set_queue_concurrency(concurrency: usize) -> Result<(), String> {
+    update_queue(|queue| {
+        queue.concurrency = concurrency.max(1);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn clear_finished_queue_jobs() -> Result<(), String> {
+    update_queue(|queue| {
+        queue.jobs.retain(|job| !job.state.is_done());
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,97 @@
+use super::{ffprobe_cmd, keyframes, render::TrimRange};
+
+/// Margin (s) kept from keyframe timestamps, which FFPROBE rounds to microseconds
+pub(crate) const KEYFRAME_EPSILON: f64 = 0.0005;
+
+/// Part of a trim range in a smart cut, joined back together with the concat demuxer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Segment {
+    /// Partial GOP at the edge of a range, re-encoded so the cut is frame accurate
+    Encode(TrimRange),
+    /// Whole GOPs copied as they are, starting at a keyframe
+    Copy(TrimRange),
+}
+
+/// Encoder producing segments that can be joined with the copied stream of the source video
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentEncoder {
+    pub(crate) args: Vec<String>,
+    /// Container of the segments, MPEG-TS repeats the parameter sets so segments from different encoders can be joined
+    pub(crate) extension: &'static str,
+}
+
+// Source codec, encoder, quality arguments, segment container
+const MATCHING_ENCODERS: [(&str, &str, &[&str], &str); 4] = [
+    ("h264", "libx264", &["-crf", "16"], "ts"),
+    ("hevc", "libx265", &["-crf", "18"], "ts"),
+    ("vp9", "libvpx-vp9", &["-crf", "20", "-b:v", "0"], "mkv"),
+    ("av1", "libaom-av1", &["-crf", "20", "-b:v", "0"], "mkv"),
+];
+
+/// Picks the encoder matching the codec of the first video stream of the file
+pub(crate) async fn matching_encoder(filepath: &str) -> Result<SegmentEncoder, String> {
+    let stream = ffprobe_cmd::probe_video_stream(filepath, "codec_name,pix_fmt").await?;
+    let codec_name = stream["codec_name"]
+        .as_str()
+        .ok_or("The video stream has no codec")?;
+
+    let (_, encoder, quality_args, extension) = MATCHING_ENCODERS
+        .iter()
+        .find(|(codec, ..)| *codec == codec_name)
+        .ok_or_else(|| format!("Smart rendering is not supported for {codec_name} video"))?;
+
+    let mut args = vec!["-c:v".to_owned(), encoder.to_string()];
+    args.extend(quality_args.iter().map(|arg| arg.to_string()));
+    // Segments must match the pixel format of the copied stream to be joined with it
+    if let Some(pix_fmt) = stream["pix_fmt"].as_str() {
+        args.extend(["-pix_fmt".to_owned(), pix_fmt.to_owned()]);
+    }
+
+    Ok(SegmentEncoder { args, extension })
+}
+
+/// Splits each trim range into the GOPs that have to be re-encoded and the ones that can be copied
+pub(crate) async fn plan_segments(
+    filepath: &str,
+    ranges: &[TrimRange],
+) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+
+    for range in ranges {
+        let keyframes = keyframes::probe_keyframes(filepath, range.start, range.end).await?;
+
+        // First keyframe at or after the start, and the last one before the end
+        let first = keyframes
+            .iter()
+            .find(|keyframe| **keyframe >= range.start - KEYFRAME_EPSILON)
+            .copied();
+        let last = keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| **keyframe < range.end - KEYFRAME_EPSILON)
+            .copied();
+
+        match (first, last) {
+            // Without a whole GOP inside the range there's nothing to copy
+            (Some(first), Some(last)) if first < last => {
+                if first - range.start > KEYFRAME_EPSILON {
+                    segments.push(Segment::Encode(TrimRange {
+                        start: range.start,
+                        end: first,
+                    }));
+                }
+                segments.push(Segment::Copy(TrimRange {
+                    start: first,
+                    end: last,
+                }));
+                segments.push(Segment::Encode(TrimRange {
+                    start: last,
+                    end: range.end,
+                }));
+            }
+            _ => segments.push(Segment::Encode(*range)),
+        }
+    }
+
+    Ok(segments)
+}
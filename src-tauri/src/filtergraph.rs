@@ -0,0 +1,383 @@
+use std::fmt::{self, Display};
+
+/// A stream that can be fed into a filter or mapped to the output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// A stream of an input file, e.g. `0:v` or `0:2`
+    Input(String),
+    /// An output of a filter chain in the graph
+    Filtered(String),
+}
+
+impl Stream {
+    /// Argument passed to `-map` to select this stream
+    pub fn map_arg(&self) -> String {
+        match self {
+            Stream::Input(specifier) => specifier.clone(),
+            Stream::Filtered(label) => format!("[{label}]"),
+        }
+    }
+
+    fn pad(&self) -> String {
+        match self {
+            Stream::Input(specifier) | Stream::Filtered(specifier) => format!("[{specifier}]"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+impl MediaKind {
+    // Audio variants of filters are prefixed with an "a", e.g. trim and atrim
+    fn filter(self, name: &str) -> String {
+        match self {
+            MediaKind::Video => name.to_owned(),
+            MediaKind::Audio => format!("a{name}"),
+        }
+    }
+}
+
+/// An audio stream to mix, with its volume and stereo position
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixInput {
+    pub stream: Stream,
+    /// Linear volume multiplier, 1 keeps the volume as it is
+    pub gain: f64,
+    /// Stereo position from -1 (left) to 1 (right), or none to leave the channels as they are
+    pub pan: Option<f64>,
+}
+
+/// Builds an FFMPEG `-filter_complex` graph out of chains of filters between labelled streams
+#[derive(Default, Debug)]
+pub struct FilterGraph {
+    chains: Vec<String>,
+    next_label: u32,
+}
+
+impl FilterGraph {
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Creates a new uniquely labelled stream to use as the output of a chain
+    pub fn stream(&mut self, prefix: &str) -> Stream {
+        let label = format!("{prefix}{}", self.next_label);
+        self.next_label += 1;
+        Stream::Filtered(label)
+    }
+
+    /// Adds a chain of filters reading from the inputs and writing to the outputs
+    pub fn chain(&mut self, inputs: &[&Stream], filters: &[String], outputs: &[&Stream]) {
+        let mut chain = String::new();
+        for input in inputs {
+            chain.push_str(&input.pad());
+        }
+        chain.push_str(&filters.join(","));
+        for output in outputs {
+            chain.push_str(&output.pad());
+        }
+        self.chains.push(chain);
+    }
+
+    /// Adds a chain from a single input to a new output stream, returning that stream
+    pub fn then(&mut self, input: &Stream, filters: &[String], prefix: &str) -> Stream {
+        let output = self.stream(prefix);
+        self.chain(&[input], filters, &[&output]);
+        output
+    }
+
+    /// Cuts the given `(start, end)` ranges in seconds out of a stream and joins them back to back
+    pub fn trim_concat(
+        &mut self,
+        source: &Stream,
+        ranges: &[(f64, f64)],
+        kind: MediaKind,
+    ) -> Stream {
+        let prefix = match kind {
+            MediaKind::Video => "v",
+            MediaKind::Audio => "a",
+        };
+
+        // A stream can only be read by one filter, so split it into one copy per range
+        let copies: Vec<Stream> = ranges.iter().map(|_| self.stream(prefix)).collect();
+        self.chain(
+            &[source],
+            &[format!("{}={}", kind.filter("split"), ranges.len())],
+            &copies.iter().collect::<Vec<_>>(),
+        );
+
+        let segments: Vec<Stream> = ranges
+            .iter()
+            .zip(&copies)
+            .map(|((start, end), copy)| {
+                self.then(
+                    copy,
+                    &[
+                        format!("{}=start={start}:end={end}", kind.filter("trim")),
+                        format!("{}=PTS-STARTPTS", kind.filter("setpts")),
+                    ],
+                    prefix,
+                )
+            })
+            .collect();
+
+        let (video_count, audio_count) = match kind {
+            MediaKind::Video => (1, 0),
+            MediaKind::Audio => (0, 1),
+        };
+        let output = self.stream(prefix);
+        self.chain(
+            &segments.iter().collect::<Vec<_>>(),
+            &[format!(
+                "concat=n={}:v={video_count}:a={audio_count}",
+                segments.len()
+            )],
+            &[&output],
+        );
+        output
+    }
+
+    /// Fades a stream in from and out to black or silence, over the timeline from `start` (s) lasting `length` (s).
+    /// Fades of 0s are left out
+    pub fn fade(
+        &mut self,
+        input: &Stream,
+        kind: MediaKind,
+        (start, length): (f64, f64),
+        fade_in: f64,
+        fade_out: f64,
+    ) -> Stream {
+        let mut filters = Vec::new();
+        if fade_in > 0.0 {
+            filters.push(format!(
+                "{}=t=in:st={start}:d={fade_in}",
+                kind.filter("fade")
+            ));
+        }
+        if fade_out > 0.0 {
+            let fade_start = start + (length - fade_out).max(0.0);
+            filters.push(format!(
+                "{}=t=out:st={fade_start}:d={fade_out}",
+                kind.filter("fade")
+            ));
+        }
+
+        if filters.is_empty() {
+            return input.clone();
+        }
+        let prefix = match kind {
+            MediaKind::Video => "v",
+            MediaKind::Audio => "a",
+        };
+        self.then(input, &filters, prefix)
+    }
+
+    /// Burns a text subtitle stream of the file into a video stream. `stream` is the position of the track among the
+    /// file's subtitle streams, and `offset` (s) is where the video starts in the file when its input was seeked
+    pub fn subtitles(
+        &mut self,
+        input: &Stream,
+        filepath: &str,
+        stream: u32,
+        offset: f64,
+    ) -> Stream {
+        let subtitles = format!("subtitles=filename={}:si={stream}", escape_option(filepath));
+        // The subtitles are timed from the start of the file, so shift the seeked video to them and back
+        let filters = if offset > 0.0 {
+            vec![
+                format!("setpts=PTS+{offset}/TB"),
+                subtitles,
+                format!("setpts=PTS-{offset}/TB"),
+            ]
+        } else {
+            vec![subtitles]
+        };
+        self.then(input, &filters, "v")
+    }
+
+    /// Mixes audio streams together after applying their gain and pan, converting the mix to `layout` if given
+    pub fn mix(&mut self, inputs: &[MixInput], layout: Option<&str>) -> Stream {
+        let mixed: Vec<Stream> = inputs
+            .iter()
+            .map(|input| {
+                let mut filters = Vec::new();
+                if input.gain != 1.0 {
+                    filters.push(format!("volume={}", input.gain));
+                }
+                if let Some(pan) = input.pan {
+                    // Balance between the channels of a stereo mix, the far channel is turned down towards silence
+                    let left = (1.0 - pan).min(1.0);
+                    let right = (1.0 + pan).min(1.0);
+                    filters.push("aformat=channel_layouts=stereo".to_owned());
+                    filters.push(format!("pan=stereo|c0={left}*c0|c1={right}*c1"));
+                }
+
+                if filters.is_empty() {
+                    input.stream.clone()
+                } else {
+                    self.then(&input.stream, &filters, "a")
+                }
+            })
+            .collect();
+
+        let mut filters = Vec::new();
+        if mixed.len() > 1 {
+            // Gains are already applied per input, so don't let amix scale the volume down by the number of inputs
+            filters.push(format!(
+                "amix=inputs={}:duration=longest:normalize=0",
+                mixed.len()
+            ));
+        }
+        if let Some(layout) = layout {
+            filters.push(format!("aformat=channel_layouts={layout}"));
+        }
+
+        match (mixed.as_slice(), filters.is_empty()) {
+            ([stream], true) => stream.clone(),
+            _ => {
+                let output = self.stream("a");
+                self.chain(&mixed.iter().collect::<Vec<_>>(), &filters, &[&output]);
+                output
+            }
+        }
+    }
+}
+
+// Escapes a value, e.g. a file path, for a filter option in the graph. It's unescaped once when the graph is
+// split into filters and again when the filter's options are split
+fn escape_option(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| -> String {
+        value
+            .chars()
+            .flat_map(|c| special.contains(&c).then_some('\\').into_iter().chain([c]))
+            .collect()
+    };
+    escape(
+        &escape(value, &['\\', '\'', ':']),
+        &['\\', '\'', '[', ']', ',', ';'],
+    )
+}
+
+impl Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.chains.join(";"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(specifier: &str, gain: f64, pan: Option<f64>) -> MixInput {
+        MixInput {
+            stream: Stream::Input(specifier.into()),
+            gain,
+            pan,
+        }
+    }
+
+    #[test]
+    fn trim_concat_splits_trims_and_joins_ranges() {
+        let mut graph = FilterGraph::default();
+        let output = graph.trim_concat(
+            &Stream::Input("0:v:0".into()),
+            &[(0.0, 1.5), (3.0, 4.0)],
+            MediaKind::Video,
+        );
+
+        assert_eq!(output.map_arg(), "[v4]");
+        assert_eq!(
+            graph.to_string(),
+            "[0:v:0]split=2[v0][v1];\
+             [v0]trim=start=0:end=1.5,setpts=PTS-STARTPTS[v2];\
+             [v1]trim=start=3:end=4,setpts=PTS-STARTPTS[v3];\
+             [v2][v3]concat=n=2:v=1:a=0[v4]"
+        );
+    }
+
+    #[test]
+    fn mix_applies_gain_and_pan_to_any_stream_indices() {
+        let mut graph = FilterGraph::default();
+        let output = graph.mix(
+            &[input("0:2", 0.5, None), input("0:4", 1.0, Some(-0.5))],
+            Some("stereo"),
+        );
+
+        assert_eq!(output.map_arg(), "[a2]");
+        assert_eq!(
+            graph.to_string(),
+            "[0:2]volume=0.5[a0];\
+             [0:4]aformat=channel_layouts=stereo,pan=stereo|c0=1*c0|c1=0.5*c1[a1];\
+             [a0][a1]amix=inputs=2:duration=longest:normalize=0,aformat=channel_layouts=stereo[a2]"
+        );
+    }
+
+    #[test]
+    fn fade_is_relative_to_the_timeline_start() {
+        let mut graph = FilterGraph::default();
+        let output = graph.fade(
+            &Stream::Input("0:1".into()),
+            MediaKind::Audio,
+            (10.0, 5.0),
+            0.5,
+            1.0,
+        );
+
+        assert_eq!(output.map_arg(), "[a0]");
+        assert_eq!(
+            graph.to_string(),
+            "[0:1]afade=t=in:st=10:d=0.5,afade=t=out:st=14:d=1[a0]"
+        );
+    }
+
+    #[test]
+    fn subtitles_escape_the_path_and_shift_seeked_video() {
+        let mut graph = FilterGraph::default();
+        let output = graph.subtitles(
+            &Stream::Input("0:v:0".into()),
+            r"C:\Videos\it's [1].mkv",
+            2,
+            30.0,
+        );
+
+        assert_eq!(output.map_arg(), "[v0]");
+        assert_eq!(
+            graph.to_string(),
+            r"[0:v:0]setpts=PTS+30/TB,subtitles=filename=C\\:\\\\Videos\\\\it\\\'s \[1\].mkv:si=2,setpts=PTS-30/TB[v0]"
+        );
+    }
+
+    #[test]
+    fn fade_without_durations_leaves_the_stream() {
+        let mut graph = FilterGraph::default();
+        let input = Stream::Input("0:v".into());
+
+        assert_eq!(
+            graph.fade(&input, MediaKind::Video, (0.0, 5.0), 0.0, 0.0),
+            input
+        );
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn mix_passes_a_single_untouched_track_through() {
+        let mut graph = FilterGraph::default();
+        let output = graph.mix(&[input("0:1", 1.0, None)], None);
+
+        assert_eq!(output, Stream::Input("0:1".into()));
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn mix_converts_a_single_track_to_the_layout() {
+        let mut graph = FilterGraph::default();
+        let output = graph.mix(&[input("0:1", 1.0, None)], Some("mono"));
+
+        assert_eq!(output.map_arg(), "[a0]");
+        assert_eq!(graph.to_string(), "[0:1]aformat=channel_layouts=mono[a0]");
+    }
+}
@@ -18,6 +18,7 @@ const FFPROBE_BIN: &'static [u8] = include_bytes!("ffmpeg/linux/ffprobe.zst");
 
 use std::{
     fs::{create_dir, File},
+    future::Future,
     io::BufWriter,
     path::PathBuf,
     sync::{Arc, OnceLock},
@@ -26,15 +27,18 @@ use std::{
 use tauri::{
     http::{self, HeaderValue},
     menu::{Menu, MenuEvent, MenuItem, Submenu},
-    App, Manager, Window, Wry,
+    App, Manager, UriSchemeResponder, Window, Wry,
 };
 
 static FFMPEG_HOME: OnceLock<PathBuf> = OnceLock::new();
 static FFPROBE_PATH: OnceLock<PathBuf> = OnceLock::new();
 static FFMPEG_PATH: OnceLock<PathBuf> = OnceLock::new();
 static TEMP_PATH: OnceLock<PathBuf> = OnceLock::new();
+static LOCAL_DATA_PATH: OnceLock<PathBuf> = OnceLock::new();
 
+mod cache;
 mod commands;
+mod filtergraph;
 mod protocols;
 
 fn get_app_temp_data_dir(app: &App) -> PathBuf {
@@ -71,6 +75,7 @@ fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
     if !local_data_path.exists() {
         create_dir(local_data_path.as_path()).expect("Failed to create app local data directory");
     }
+    LOCAL_DATA_PATH.set(local_data_path.clone()).unwrap();
 
     let mut ffmpeg_home = local_data_path;
     ffmpeg_home.push("ffmpeg");
@@ -108,6 +113,8 @@ fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
     FFPROBE_PATH.set(ffprobe_path).unwrap();
     FFMPEG_PATH.set(ffmpeg_path).unwrap();
 
+    commands::render_queue::restore_queue(app.handle().clone());
+
     Ok(())
 }
 
@@ -118,8 +125,12 @@ fn create_menu(app: &App) -> Menu<Wry> {
     submenu_file.append_items(&[&new_btn, &quit_btn]).unwrap();
 
     let prefs_btn = MenuItem::with_id(app, "prefs", "Preferences", true, None::<&str>).unwrap();
+    let clear_cache_btn =
+        MenuItem::with_id(app, "clear_cache", "Clear Media Cache", true, None::<&str>).unwrap();
     let submenu_options = Submenu::new(app, "Options", true).unwrap();
-    submenu_options.append_items(&[&prefs_btn]).unwrap();
+    submenu_options
+        .append_items(&[&prefs_btn, &clear_cache_btn])
+        .unwrap();
 
     let zoom_in_btn = MenuItem::with_id(app, "zoom_in", "Zoom In", true, None::<&str>).unwrap();
     let zoom_out_btn = MenuItem::with_id(app, "zoom_out", "Zoom Out", true, None::<&str>).unwrap();
@@ -156,6 +167,22 @@ fn handle_menu(window: &Window, event: MenuEvent) {
     }
 }
 
+// Responds with the protocol's response, or its error as a bad request
+async fn respond_to_protocol(
+    response: impl Future<Output = Result<http::Response<Vec<u8>>, String>>,
+    responder: UriSchemeResponder,
+) {
+    let mut res = response.await.unwrap_or_else(|e| {
+        http::Response::builder()
+            .status(400)
+            .body(Vec::from(e))
+            .unwrap()
+    });
+    res.headers_mut()
+        .append("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+    responder.respond(res)
+}
+
 fn main() {
     let runtime = Arc::new(
         tokio::runtime::Builder::new_multi_thread()
@@ -169,19 +196,37 @@ fn main() {
         .register_asynchronous_uri_scheme_protocol("extract-audio", {
             let runtime = Arc::clone(&runtime);
             move |_app, req, resp| {
-                runtime.spawn(async move {
-                    let mut res = protocols::extract_audio::extract_audio_protocol(req)
-                        .await
-                        .unwrap_or_else(|e| {
-                            http::Response::builder()
-                                .status(400)
-                                .body(Vec::from(e))
-                                .unwrap()
-                        });
-                    res.headers_mut()
-                        .append("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
-                    resp.respond(res)
-                });
+                runtime.spawn(respond_to_protocol(
+                    protocols::extract_audio::extract_audio_protocol(req),
+                    resp,
+                ));
+            }
+        })
+        .register_asynchronous_uri_scheme_protocol("preview", {
+            let runtime = Arc::clone(&runtime);
+            move |_app, req, resp| {
+                runtime.spawn(respond_to_protocol(
+                    protocols::preview::preview_protocol(req),
+                    resp,
+                ));
+            }
+        })
+        .register_asynchronous_uri_scheme_protocol("thumbnail", {
+            let runtime = Arc::clone(&runtime);
+            move |_app, req, resp| {
+                runtime.spawn(respond_to_protocol(
+                    protocols::thumbnail::thumbnail_protocol(req),
+                    resp,
+                ));
+            }
+        })
+        .register_asynchronous_uri_scheme_protocol("waveform", {
+            let runtime = Arc::clone(&runtime);
+            move |_app, req, resp| {
+                runtime.spawn(respond_to_protocol(
+                    protocols::waveform::waveform_protocol(req),
+                    resp,
+                ));
             }
         })
         .setup(|app| {
@@ -198,13 +243,27 @@ fn main() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_shell::init())
         .invoke_handler(tauri::generate_handler![
+            commands::clear_media_cache::clear_media_cache,
             commands::close_splashscreen::close_splashscreen,
             commands::ffprobe_cmd::ffprobe_cmd,
             commands::toggle_fullscreen::toggle_fullscreen,
             commands::get_encoders::get_encoders,
             commands::get_hwaccels::get_hwaccels,
+            commands::keyframes::snap_to_keyframes,
+            commands::preview::prepare_preview,
             commands::render::start_render,
+            commands::render::start_size_limited_render,
+            commands::render::set_accept_current_attempt,
+            commands::render::list_renders,
             commands::render::cancel_render,
+            commands::render_queue::get_render_queue,
+            commands::render_queue::enqueue_render,
+            commands::render_queue::remove_queue_job,
+            commands::render_queue::move_queue_job,
+            commands::render_queue::set_queue_job_paused,
+            commands::render_queue::set_queue_paused,
+            commands::render_queue::set_queue_concurrency,
+            commands::render_queue::clear_finished_queue_jobs,
             commands::show_in_folder::show_in_folder
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,249 @@
+use std::{
+    fs::{self, File},
+    io::{Read as _, Seek as _, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tauri::http;
+use tokio::task::JoinHandle;
+
+/// Most bytes sent for an open-ended range, so playback can start before the whole file is read
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Fewest bytes sent of a file still being written, unless the range asked for is smaller
+const MIN_GROWING_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// How often a file still being written is checked for the bytes asked for
+const GROWING_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Inclusive range of bytes to send, or none when the requested range can't be satisfied
+fn parse_range(header: &str, length: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // Multipart responses aren't needed by media elements, so only the first range is sent
+    let spec = spec.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+
+    let last = length.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range, the last N bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (length.saturating_sub(suffix), last)
+        }
+        (start, "") => {
+            let start: u64 = start.parse().ok()?;
+            (start, start.saturating_add(MAX_CHUNK_SIZE - 1).min(last))
+        }
+        (start, end) => {
+            let end: u64 = end.parse().ok()?;
+            (start.parse().ok()?, end.min(last))
+        }
+    };
+
+    (start <= end && start < length).then_some((start, end))
+}
+
+// Reads the inclusive range of bytes from the file
+fn read_range(path: &Path, start: u64, end: u64) -> Result<Vec<u8>, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut data = vec![0; (end - start + 1) as usize];
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| e.to_string())?;
+    file.read_exact(&mut data).map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+// Range asked for, or the first chunk without a Range header so the whole file is never read into memory at once
+fn requested_range(req: &http::Request<Vec<u8>>) -> &str {
+    req.headers()
+        .get("Range")
+        .and_then(|header| header.to_str().ok())
+        .unwrap_or("bytes=0-")
+}
+
+fn partial_response(
+    content_type: &str,
+    data: Vec<u8>,
+    content_range: String,
+) -> Result<http::Response<Vec<u8>>, String> {
+    http::Response::builder()
+        .header("Content-Type", content_type)
+        .header("Accept-Ranges", "bytes")
+        .status(206)
+        .header("Content-Length", data.len())
+        .header("Content-Range", content_range)
+        .body(data)
+        .map_err(|e| e.to_string())
+}
+
+/// Responds with the bytes of a file requested by the Range header, or its first chunk without one
+pub(crate) fn serve_file(
+    req: &http::Request<Vec<u8>>,
+    path: &Path,
+    content_type: &str,
+) -> Result<http::Response<Vec<u8>>, String> {
+    let length = fs::metadata(path).map_err(|e| e.to_string())?.len();
+
+    let Some((start, end)) = parse_range(requested_range(req), length) else {
+        return http::Response::builder()
+            .header("Content-Type", content_type)
+            .header("Accept-Ranges", "bytes")
+            .status(416)
+            .header("Content-Range", format!("bytes */{length}"))
+            .body(Vec::new())
+            .map_err(|e| e.to_string());
+    };
+
+    let data = read_range(path, start, end)?;
+    partial_response(content_type, data, format!("bytes {start}-{end}/{length}"))
+}
+
+/// Responds with the requested bytes of a file still being written to `partial_path`, as soon as they're written.
+/// The length isn't known until `task` has finished, so ranges are sent without it until then
+pub(crate) async fn serve_growing_file(
+    req: &http::Request<Vec<u8>>,
+    partial_path: &Path,
+    task: JoinHandle<Result<PathBuf, String>>,
+    content_type: &str,
+) -> Result<http::Response<Vec<u8>>, String> {
+    let range = requested_range(req);
+    // Suffix ranges count back from the end, which has to be written first
+    let suffix = range
+        .trim()
+        .strip_prefix("bytes=")
+        .is_some_and(|spec| spec.trim_start().starts_with('-'));
+
+    loop {
+        if task.is_finished() {
+            let path = task.await.map_err(|e| e.to_string())??;
+            return serve_file(req, &path, content_type);
+        }
+
+        // The partial file doesn't exist until the task starts writing it, and is gone once it's renamed
+        let written = fs::metadata(partial_path).map_or(0, |metadata| metadata.len());
+        let available = parse_range(range, written).filter(|_| !suffix);
+        if let Some((start, end)) = available {
+            // Waits for a full chunk unless all of the range asked for is already there,
+            // so the player isn't sent the file a sliver at a time
+            if end + 1 < written || end - start + 1 >= MIN_GROWING_CHUNK_SIZE {
+                // Read straight away, since the partial file is renamed once complete
+                if let Ok(data) = read_range(partial_path, start, end) {
+                    return partial_response(content_type, data, format!("bytes {start}-{end}/*"));
+                }
+            }
+        }
+
+        tokio::time::sleep(GROWING_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_ended_range_is_capped_at_the_chunk_size() {
+        assert_eq!(
+            parse_range("bytes=100-", 10 * MAX_CHUNK_SIZE),
+            Some((100, 100 + MAX_CHUNK_SIZE - 1))
+        );
+        assert_eq!(parse_range("bytes=100-", 1000), Some((100, 999)));
+    }
+
+    #[test]
+    fn open_ended_range_near_the_largest_offset_does_not_overflow() {
+        assert_eq!(parse_range(&format!("bytes={}-", u64::MAX), 1000), None);
+        assert_eq!(
+            parse_range(&format!("bytes={}-", u64::MAX - 1), u64::MAX),
+            Some((u64::MAX - 1, u64::MAX - 1))
+        );
+    }
+
+    #[test]
+    fn range_starting_past_the_end_is_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=1000-2000", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn suffix_range_is_clamped_to_the_file() {
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some((0, 999)));
+    }
+
+    fn request(range: Option<&str>) -> http::Request<Vec<u8>> {
+        let mut builder = http::Request::builder().uri("extract-audio://localhost/");
+        if let Some(range) = range {
+            builder = builder.header("Range", range);
+        }
+        builder.body(Vec::new()).unwrap()
+    }
+
+    fn header<'a>(response: &'a http::Response<Vec<u8>>, name: &str) -> &'a str {
+        response.headers()[name].to_str().unwrap()
+    }
+
+    #[test]
+    fn request_without_a_range_is_sent_the_first_chunk() {
+        let path = std::env::temp_dir().join("byte-range-first-chunk.bin");
+        fs::write(&path, vec![7; (MAX_CHUNK_SIZE + 10) as usize]).unwrap();
+
+        let response = serve_file(&request(None), &path, "audio/mpeg").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.body().len() as u64, MAX_CHUNK_SIZE);
+        assert_eq!(
+            header(&response, "Content-Range"),
+            format!("bytes 0-{}/{}", MAX_CHUNK_SIZE - 1, MAX_CHUNK_SIZE + 10)
+        );
+    }
+
+    #[tokio::test]
+    async fn growing_file_is_served_before_it_is_complete() {
+        let partial_path = std::env::temp_dir().join("byte-range-growing.bin.part");
+        fs::write(&partial_path, vec![1; MIN_GROWING_CHUNK_SIZE as usize]).unwrap();
+        let task = tokio::spawn(std::future::pending());
+
+        let response = serve_growing_file(
+            &request(Some("bytes=0-")),
+            &partial_path,
+            task,
+            "audio/mpeg",
+        )
+        .await
+        .unwrap();
+        fs::remove_file(&partial_path).unwrap();
+
+        assert_eq!(response.status(), 206);
+        assert_eq!(
+            header(&response, "Content-Range"),
+            format!("bytes 0-{}/*", MIN_GROWING_CHUNK_SIZE - 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn growing_file_is_served_whole_once_complete() {
+        let path = std::env::temp_dir().join("byte-range-complete.bin");
+        fs::write(&path, [1, 2, 3]).unwrap();
+        let task = tokio::spawn(std::future::ready(Ok(path.clone())));
+        while !task.is_finished() {
+            tokio::task::yield_now().await;
+        }
+
+        let response = serve_growing_file(
+            &request(Some("bytes=-2")),
+            &path.with_extension("bin.part"),
+            task,
+            "audio/mpeg",
+        )
+        .await
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.body(), &[2, 3]);
+        assert_eq!(header(&response, "Content-Range"), "bytes 1-2/3");
+    }
+}
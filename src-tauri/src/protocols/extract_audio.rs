@@ -2,9 +2,10 @@ use std::{error::Error, process::Stdio, string::FromUtf8Error};
 
 use serde::{Deserialize, Serialize};
 use tauri::http::{self, HeaderValue};
-use tokio::{io::AsyncReadExt as _, process::Command};
+use tokio::process::Command;
 
-use crate::FFMPEG_PATH;
+use super::byte_range;
+use crate::{cache, commands::ffprobe_cmd, FFMPEG_PATH};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ExtractAudioParams {
@@ -102,7 +103,171 @@ impl ExtractAudioParams {
     }
 }
 
-// extract-audio:///video_source/audio_track_index
+/// Container and codec an audio track is extracted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OutputFormat {
+    /// Encoder, or copy to keep the codec of the source
+    codec: &'static str,
+    muxer: &'static str,
+    muxer_args: &'static [&'static str],
+    extension: &'static str,
+    content_type: &'static str,
+}
+
+const M4A: OutputFormat = OutputFormat {
+    codec: "copy",
+    muxer: "ipod",
+    // Fragmented, so the track can be played while it's still being written
+    muxer_args: &[
+        "-movflags",
+        "+empty_moov+default_base_moof",
+        "-frag_duration",
+        "1000000",
+    ],
+    extension: "m4a",
+    content_type: "audio/mp4",
+};
+const WEBM: OutputFormat = OutputFormat {
+    codec: "copy",
+    muxer: "webm",
+    muxer_args: &[],
+    extension: "webm",
+    content_type: "audio/webm",
+};
+const MP3: OutputFormat = OutputFormat {
+    codec: "copy",
+    muxer: "mp3",
+    muxer_args: &[],
+    extension: "mp3",
+    content_type: "audio/mpeg",
+};
+const FLAC: OutputFormat = OutputFormat {
+    codec: "copy",
+    muxer: "flac",
+    muxer_args: &[],
+    extension: "flac",
+    content_type: "audio/flac",
+};
+
+// Source codecs the player can decode, copied into a container it can read
+const COPY_FORMATS: [(&str, OutputFormat); 5] = [
+    ("aac", M4A),
+    ("opus", WEBM),
+    ("vorbis", WEBM),
+    ("mp3", MP3),
+    ("flac", FLAC),
+];
+
+/// Codec tracks the player can't decode are re-encoded to, set with `?fallback=` on the URL
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum FallbackCodec {
+    #[default]
+    Mp3,
+    Aac,
+    Opus,
+}
+
+impl FallbackCodec {
+    fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let fallback = query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("fallback="));
+
+        match fallback {
+            None | Some("mp3") => Ok(Self::Mp3),
+            Some("aac") => Ok(Self::Aac),
+            Some("opus") => Ok(Self::Opus),
+            Some(codec) => Err(format!("Unsupported fallback codec: {codec}")),
+        }
+    }
+
+    fn format(self) -> OutputFormat {
+        match self {
+            FallbackCodec::Mp3 => OutputFormat {
+                codec: "libmp3lame",
+                ..MP3
+            },
+            FallbackCodec::Aac => OutputFormat {
+                codec: "aac",
+                ..M4A
+            },
+            FallbackCodec::Opus => OutputFormat {
+                codec: "libopus",
+                ..WEBM
+            },
+        }
+    }
+}
+
+// Copies the track when its codec can be played, otherwise re-encodes it with the fallback
+async fn pick_format(
+    video_source: &str,
+    audio_track_index: u32,
+    fallback: FallbackCodec,
+) -> Result<OutputFormat, String> {
+    let stream =
+        ffprobe_cmd::probe_stream(video_source, &audio_track_index.to_string(), "codec_name")
+            .await?
+            .ok_or_else(|| format!("The file has no stream {audio_track_index}"))?;
+    let codec_name = stream["codec_name"].as_str().unwrap_or_default();
+
+    Ok(COPY_FORMATS
+        .iter()
+        .find(|(codec, _)| *codec == codec_name)
+        .map(|(_, format)| *format)
+        .unwrap_or_else(|| fallback.format()))
+}
+
+// Extracts the audio track into the cache in the background, where reopening the video finds it
+async fn extract_to_cache(
+    video_source: &str,
+    audio_track_index: u32,
+    format: OutputFormat,
+) -> Result<cache::Entry, String> {
+    let variant = format!(
+        "audio-{audio_track_index}-{}-{}",
+        format.codec, format.muxer
+    );
+    let source = video_source.to_owned();
+    cache::get_or_spawn(
+        video_source,
+        &variant,
+        format.extension,
+        move |path| async move {
+            let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+            command
+                .args(["-v", "error", "-y", "-i", &source])
+                .args([
+                    "-map",
+                    &format!("0:{}", audio_track_index),
+                    "-c:a",
+                    format.codec,
+                ])
+                .args(format.muxer_args)
+                .args(["-f", format.muxer])
+                .arg(&path);
+            #[cfg(target_os = "windows")]
+            command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+
+            let output = command
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .map_err(|e| e.to_string())?;
+            if !output.status.success() {
+                return Err(format!(
+                    "Failed to extract audio track {audio_track_index}: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            Ok(())
+        },
+    )
+    .await
+}
+
+// extract-audio:///video_source/audio_track_index?fallback=mp3|aac|opus
 pub async fn extract_audio_protocol(
     req: http::Request<Vec<u8>>,
 ) -> Result<http::Response<Vec<u8>>, String> {
@@ -110,52 +275,18 @@ pub async fn extract_audio_protocol(
         video_source,
         audio_track_index,
     } = ExtractAudioParams::from_str(req.uri().path()).map_err(|e| e.to_string())?;
+    let fallback = FallbackCodec::from_query(req.uri().query())?;
 
-    let mut data: Vec<u8> = Vec::new();
-
-    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
-    command
-        .args([
-            "-i",
-            &video_source,
-            "-v",
-            "quiet",
-            "-map",
-            &format!("0:{}", audio_track_index), // Replace mp3 with some container supporting the codec
-            "-f",
-            "mp3",
-        ])
-        .arg(&"pipe:2");
-    #[cfg(target_os = "windows")]
-    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
-
-    let child = command
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| e.to_string())?;
-
-    child
-        .stderr
-        .unwrap()
-        .read_to_end(&mut data)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let data_length = data.len();
-    let mut req = http::Response::new(data);
-
-    let headers = req.headers_mut();
-    headers.append("Content-Type", HeaderValue::from_static("audio/mp3"));
-    headers.append("Accept-Ranges", HeaderValue::from_static("bytes"));
-    headers.append(
-        "Content-Length",
-        HeaderValue::from_str(data_length.to_string().as_str()).unwrap(),
-    );
-    headers.append(
-        "Content-Range",
-        HeaderValue::from_str(format!("bytes */{}", data_length).as_str()).unwrap(),
-    );
-    headers.append("Connection", HeaderValue::from_static("Keep-Alive"));
+    let format = pick_format(&video_source, audio_track_index, fallback).await?;
+    // Requests made while the track is being extracted are sent what's been written so far
+    let mut res = match extract_to_cache(&video_source, audio_track_index, format).await? {
+        cache::Entry::Ready(path) => byte_range::serve_file(&req, &path, format.content_type)?,
+        cache::Entry::Creating { partial_path, task } => {
+            byte_range::serve_growing_file(&req, &partial_path, task, format.content_type).await?
+        }
+    };
+    res.headers_mut()
+        .append("Connection", HeaderValue::from_static("Keep-Alive"));
 
-    Ok(req)
+    Ok(res)
 }
@@ -0,0 +1,214 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use tauri::http;
+use tokio::{
+    process::{Child, Command},
+    sync::Mutex,
+};
+
+use super::preview;
+use crate::{cache, commands::ffprobe_cmd, FFMPEG_PATH, TEMP_PATH};
+
+/// Length (s) of each segment, every segment starts with a keyframe at a multiple of it
+const SEGMENT_SECONDS: f64 = 4.0;
+
+/// Segments past the newest one written that are still worth waiting for instead of seeking
+const LOOKAHEAD_SEGMENTS: u32 = 3;
+
+/// Longest a request waits for its segment to be transcoded
+const SEGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// FFMPEG process transcoding segments of a file, from `first_segment` onwards
+struct Transcode {
+    source: String,
+    first_segment: u32,
+    child: Child,
+}
+
+// Only the file in the player is transcoded, seeking elsewhere or opening another file replaces it
+static TRANSCODE: LazyLock<Mutex<Option<Transcode>>> = LazyLock::new(Default::default);
+
+// Segments of each file are kept in their own directory, named after the file's path and modification time
+fn segment_dir(source: &str) -> Result<PathBuf, String> {
+    let hash = cache::source_hash(source, "hls")?;
+    Ok(TEMP_PATH
+        .get()
+        .unwrap()
+        .join("hls")
+        .join(format!("{hash:016x}")))
+}
+
+fn segment_path(dir: &Path, segment: u32) -> PathBuf {
+    dir.join(format!("segment{segment}.m4s"))
+}
+
+/// Media playlist listing every segment of the file, which are transcoded when requested
+async fn playlist(source: &str) -> Result<String, String> {
+    let duration = ffprobe_cmd::probe_duration(source).await?;
+    let count = (duration / SEGMENT_SECONDS).ceil().max(1.0) as u32;
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{}\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n#EXT-X-MAP:URI=\"init.mp4\"\n",
+        SEGMENT_SECONDS.ceil()
+    );
+    for segment in 0..count {
+        let length = (duration - segment as f64 * SEGMENT_SECONDS).min(SEGMENT_SECONDS);
+        let _ = write!(playlist, "#EXTINF:{length:.6},\nsegment/{segment}.m4s\n");
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+
+    Ok(playlist)
+}
+
+// Starts transcoding the file into the directory from the start of `first_segment`
+fn spawn_transcode(source: &str, dir: &Path, first_segment: u32) -> Result<Child, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let start = (first_segment as f64 * SEGMENT_SECONDS).to_string();
+
+    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+    command
+        .args(["-v", "error", "-y", "-ss", &start, "-i", source])
+        .args(preview::proxy_encoder_args())
+        .args([
+            "-force_key_frames",
+            &format!("expr:gte(t,n_forced*{SEGMENT_SECONDS})"),
+            // Timestamps carry on from where the segment is in the playlist
+            "-output_ts_offset",
+            &start,
+            "-f",
+            "hls",
+            "-hls_time",
+            &SEGMENT_SECONDS.to_string(),
+            "-hls_list_size",
+            "0",
+            "-hls_segment_type",
+            "fmp4",
+            "-hls_fmp4_init_filename",
+            "init.mp4",
+            "-start_number",
+            &first_segment.to_string(),
+            // Segments are renamed once complete, so a partly written one is never served
+            "-hls_flags",
+            "temp_file+independent_segments",
+            "-hls_segment_filename",
+        ])
+        .arg(dir.join("segment%d.m4s"))
+        .arg(dir.join("transcode.m3u8"));
+    #[cfg(target_os = "windows")]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())
+}
+
+// Makes sure a transcode is running that will soon write the segment, replacing one that's too far away from it
+async fn ensure_transcoding(source: &str, dir: &Path, segment: u32) -> Result<(), String> {
+    let mut transcode = TRANSCODE.lock().await;
+
+    if let Some(current) = transcode.as_mut() {
+        let running = matches!(current.child.try_wait(), Ok(None));
+        // Newest segment written since it started
+        let newest = (current.first_segment..)
+            .take_while(|segment| segment_path(dir, *segment).exists())
+            .last()
+            .unwrap_or(current.first_segment);
+
+        if current.source == source
+            && running
+            && (current.first_segment..=newest + LOOKAHEAD_SEGMENTS).contains(&segment)
+        {
+            return Ok(());
+        }
+    }
+
+    // Stop the stale transcode so it stops competing with the new one for the CPU
+    if let Some(mut stale) = transcode.take() {
+        let _ = stale.child.start_kill();
+        // Segments of other files won't be played again
+        if stale.source != source {
+            if let Ok(stale_dir) = segment_dir(&stale.source) {
+                let _ = fs::remove_dir_all(stale_dir);
+            }
+        }
+    }
+
+    *transcode = Some(Transcode {
+        source: source.to_owned(),
+        first_segment: segment,
+        child: spawn_transcode(source, dir, segment)?,
+    });
+    Ok(())
+}
+
+// Waits for the transcode to write the file, failing if it stops first
+async fn wait_for(path: &Path) -> Result<(), String> {
+    let started = Instant::now();
+    while !path.exists() {
+        if started.elapsed() > SEGMENT_TIMEOUT {
+            return Err(format!("Timed out transcoding {}", path.display()));
+        }
+
+        let stopped = TRANSCODE
+            .lock()
+            .await
+            .as_mut()
+            .is_none_or(|transcode| !matches!(transcode.child.try_wait(), Ok(None)));
+        // The last segment may be renamed into place just after the process exits
+        if stopped && !path.exists() {
+            return Err(format!("Transcoding stopped before {}", path.display()));
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+/// Responds to `playlist.m3u8`, `init.mp4` and `segment/N.m4s` requests of a file's segmented preview
+pub(crate) async fn serve(source: &str, file: &str) -> Result<http::Response<Vec<u8>>, String> {
+    let response = |content_type: &str, body: Vec<u8>| {
+        http::Response::builder()
+            .header("Content-Type", content_type)
+            .body(body)
+            .map_err(|e| e.to_string())
+    };
+
+    if file == "playlist.m3u8" {
+        return response(
+            "application/vnd.apple.mpegurl",
+            playlist(source).await?.into_bytes(),
+        );
+    }
+
+    let dir = segment_dir(source)?;
+    let (path, segment) = match file.strip_prefix("segment/") {
+        Some(name) => {
+            let segment: u32 = name
+                .strip_suffix(".m4s")
+                .and_then(|segment| segment.parse().ok())
+                .ok_or_else(|| format!("Invalid segment {name:?}"))?;
+            (segment_path(&dir, segment), segment)
+        }
+        // The player fetches the init segment first, before the segment at the playhead
+        None if file == "init.mp4" => (dir.join("init.mp4"), 0),
+        None => return Err(format!("Unrecognized preview file {file:?}")),
+    };
+
+    if !path.exists() {
+        ensure_transcoding(source, &dir, segment).await?;
+        wait_for(&path).await?;
+    }
+
+    response("video/mp4", fs::read(&path).map_err(|e| e.to_string())?)
+}
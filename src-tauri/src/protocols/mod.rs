@@ -1 +1,6 @@
+mod byte_range;
 pub mod extract_audio;
+mod hls;
+pub mod preview;
+pub mod thumbnail;
+pub mod waveform;
@@ -0,0 +1,210 @@
+use std::{path::PathBuf, process::Stdio};
+
+use serde::Serialize;
+use serde_json::Value;
+use tauri::http;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, BufReader},
+    process::Command,
+};
+
+use super::{byte_range, hls};
+use crate::{cache, commands::ffprobe_cmd, FFMPEG_PATH};
+
+/// Tallest the proxy is scaled down to
+const PROXY_HEIGHT: u32 = 540;
+
+const PROXY_VARIANT: &str = "preview-h264-540";
+
+// Codecs and pixel formats every web view can decode
+const WEB_VIDEO_CODECS: [&str; 4] = ["h264", "vp8", "vp9", "av1"];
+const WEB_PIXEL_FORMATS: [&str; 2] = ["yuv420p", "yuvj420p"];
+const WEB_AUDIO_CODECS: [&str; 5] = ["aac", "mp3", "opus", "vorbis", "flac"];
+
+/// What the player should load for a file
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PreviewSource {
+    /// The file can be played as it is
+    Original,
+    /// The file is played through a proxy from the preview protocol
+    Proxy,
+    /// The file is played from an HLS playlist from the preview protocol, transcoded around the playhead
+    Segmented,
+}
+
+/// Progress of a proxy transcode
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PreviewProgress {
+    /// Fraction of the proxy transcoded, from 0 to 1
+    Transcoding {
+        progress: f64,
+    },
+    Ready,
+}
+
+/// Maps the first video and audio stream, encoding them to low resolution H.264 and AAC
+pub(super) fn proxy_encoder_args() -> Vec<String> {
+    [
+        "-map",
+        "0:v:0",
+        "-map",
+        "0:a:0?",
+        "-vf",
+        &format!("scale=-2:'min({PROXY_HEIGHT},ih)',format=yuv420p"),
+        "-c:v",
+        "libx264",
+        "-preset",
+        "veryfast",
+        "-crf",
+        "28",
+        "-c:a",
+        "aac",
+        "-b:a",
+        "128k",
+        "-ac",
+        "2",
+    ]
+    .map(str::to_owned)
+    .to_vec()
+}
+
+/// Whether the file's container and the codecs of its first video and audio streams can be played by the web view
+pub(crate) async fn is_web_playable(filepath: &str) -> Result<bool, String> {
+    let json = ffprobe_cmd::probe_entries(
+        filepath,
+        &[],
+        "format=format_name:stream=codec_type,codec_name,pix_fmt",
+    )
+    .await?;
+
+    // Matroska is reported the same as WebM, so go by the extension
+    let format_name = json["format"]["format_name"].as_str().unwrap_or_default();
+    let container_playable = format_name.contains("mp4")
+        || (format_name.contains("webm") && filepath.to_lowercase().ends_with(".webm"));
+
+    let streams = json["streams"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let first_stream = |codec_type: &str| {
+        streams
+            .iter()
+            .find(|stream| stream["codec_type"].as_str() == Some(codec_type))
+    };
+    let video_playable = first_stream("video").is_some_and(|stream: &Value| {
+        WEB_VIDEO_CODECS.contains(&stream["codec_name"].as_str().unwrap_or_default())
+            && WEB_PIXEL_FORMATS.contains(&stream["pix_fmt"].as_str().unwrap_or_default())
+    });
+    let audio_playable = first_stream("audio").is_none_or(|stream| {
+        WEB_AUDIO_CODECS.contains(&stream["codec_name"].as_str().unwrap_or_default())
+    });
+
+    Ok(container_playable && video_playable && audio_playable)
+}
+
+// Transcodes a low resolution H.264/AAC copy of the file to `path`, reporting its progress
+async fn transcode_proxy(
+    filepath: &str,
+    path: PathBuf,
+    on_progress: &(impl Fn(PreviewProgress) + Sync),
+) -> Result<(), String> {
+    let duration = ffprobe_cmd::probe_duration(filepath).await?;
+
+    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+    command
+        .args(["-v", "error", "-y", "-i", filepath])
+        .args(proxy_encoder_args())
+        // Puts the index at the start so the player can seek before reading the whole file
+        .args(["-movflags", "+faststart", "-f", "mp4"])
+        .args(["-progress", "pipe:1"])
+        .arg(&path);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let mut stderr = String::new();
+    let mut child_stderr = child.stderr.take().unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let read_progress = async {
+        while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+            let out_time_us = line
+                .strip_prefix("out_time_us=")
+                .and_then(|value| value.trim().parse::<f64>().ok());
+            if let Some(out_time_us) = out_time_us {
+                on_progress(PreviewProgress::Transcoding {
+                    progress: (out_time_us / 1e6 / duration).clamp(0.0, 1.0),
+                });
+            }
+        }
+        Ok::<_, String>(())
+    };
+    let (progress_result, stderr_result) =
+        tokio::join!(read_progress, child_stderr.read_to_string(&mut stderr));
+    progress_result?;
+    stderr_result.map_err(|e| e.to_string())?;
+
+    let status = child.wait().await.map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!(
+            "Failed to transcode the preview: {}",
+            stderr.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the proxy of a file the web view can't play, returning which source the player should load.
+/// With `segmented`, the file is instead transcoded in segments as they're played
+pub(crate) async fn prepare_preview(
+    filepath: &str,
+    segmented: bool,
+    on_progress: impl Fn(PreviewProgress) + Sync,
+) -> Result<PreviewSource, String> {
+    if is_web_playable(filepath).await? {
+        return Ok(PreviewSource::Original);
+    }
+    if segmented {
+        return Ok(PreviewSource::Segmented);
+    }
+
+    let on_progress = &on_progress;
+    cache::get_or_create(filepath, PROXY_VARIANT, "mp4", |path| async move {
+        transcode_proxy(filepath, path, on_progress).await
+    })
+    .await?;
+    on_progress(PreviewProgress::Ready);
+
+    Ok(PreviewSource::Proxy)
+}
+
+// preview:///video_source for the proxy, or preview:///video_source/playlist.m3u8 for the segmented preview
+pub async fn preview_protocol(
+    req: http::Request<Vec<u8>>,
+) -> Result<http::Response<Vec<u8>>, String> {
+    let path = req.uri().path();
+    let rest = path.strip_prefix('/').unwrap_or(path);
+    let (video_source, file) = match rest.split_once('/') {
+        Some((video_source, file)) => (video_source, Some(file)),
+        None => (rest, None),
+    };
+    let video_source = urlencoding::decode(video_source)
+        .map_err(|e| format!("Invalid video source provided in {path:?}: {e}"))?;
+
+    if let Some(file) = file {
+        return hls::serve(&video_source, file).await;
+    }
+
+    let proxy = cache::get(&video_source, PROXY_VARIANT, "mp4")?
+        .ok_or_else(|| format!("No preview has been made of {video_source}"))?;
+
+    byte_range::serve_file(&req, &proxy, "video/mp4")
+}
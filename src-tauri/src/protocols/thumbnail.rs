@@ -0,0 +1,303 @@
+use std::{fs, path::Path, process::Stdio};
+
+use serde::Serialize;
+use tauri::http;
+use tokio::process::Command;
+
+use crate::{
+    cache,
+    commands::{downscale, ffprobe_cmd},
+    filtergraph::{FilterGraph, Stream},
+    FFMPEG_PATH,
+};
+
+/// Most frames a sprite sheet can hold, each one is a separate seek
+const MAX_SPRITE_FRAMES: u32 = 200;
+
+/// Widest thumbnail that can be requested
+const MAX_WIDTH: u32 = 3840;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ImageFormat {
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    fn encoder_args(self) -> [&'static str; 4] {
+        match self {
+            ImageFormat::Jpeg => ["-c:v", "mjpeg", "-q:v", "3"],
+            ImageFormat::Webp => ["-c:v", "libwebp", "-quality", "75"],
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ThumbnailKind {
+    /// Single frame at a timestamp in seconds
+    Frame { time: f64 },
+    /// Evenly spaced frames tiled into one image
+    Sprite { count: u32, columns: u32 },
+    /// Where each frame of the sprite sheet is and its timestamp
+    SpriteIndex { count: u32, columns: u32 },
+}
+
+#[derive(Debug)]
+struct ThumbnailParams {
+    video_source: String,
+    kind: ThumbnailKind,
+    width: u32,
+    format: ImageFormat,
+}
+
+impl ThumbnailParams {
+    // /video_source/frame?time=T, /video_source/sprite?count=N&columns=C or /video_source/sprite.json?count=N&columns=C,
+    // all taking &width=W&format=jpeg|webp
+    fn from_uri(uri: &http::Uri) -> Result<Self, String> {
+        let path = uri.path();
+        let (video_source, kind) = path
+            .strip_prefix('/')
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(|| format!("Missing delimiter in {path:?}"))?;
+        let video_source = urlencoding::decode(video_source)
+            .map_err(|e| format!("Invalid video source provided in {video_source:?}: {e}"))?;
+
+        let query: Vec<(&str, &str)> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .collect();
+        let value = |key: &str| {
+            query
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| *value)
+        };
+        let number = |key: &str, default: u32, max: u32| -> Result<u32, String> {
+            value(key).map_or(Ok(default), |value| {
+                value
+                    .parse()
+                    .ok()
+                    .filter(|number| (1..=max).contains(number))
+                    .ok_or_else(|| format!("{key} must be between 1 and {max}, got {value}"))
+            })
+        };
+
+        let count = number("count", 10, MAX_SPRITE_FRAMES)?;
+        let columns = number("columns", count, MAX_SPRITE_FRAMES)?;
+        let kind = match kind {
+            "frame" => ThumbnailKind::Frame {
+                time: value("time")
+                    .and_then(|time| time.parse().ok())
+                    .filter(|time: &f64| time.is_finite() && *time >= 0.0)
+                    .ok_or("A frame needs a time of at least 0")?,
+            },
+            "sprite" => ThumbnailKind::Sprite { count, columns },
+            "sprite.json" => ThumbnailKind::SpriteIndex { count, columns },
+            kind => return Err(format!("Unrecognized thumbnail kind {kind:?}")),
+        };
+
+        let format = match value("format") {
+            None | Some("jpeg") => ImageFormat::Jpeg,
+            Some("webp") => ImageFormat::Webp,
+            Some(format) => return Err(format!("Unsupported image format: {format}")),
+        };
+
+        Ok(Self {
+            video_source: video_source.into_owned(),
+            kind,
+            // Encoders need even dimensions
+            width: (number("width", 320, MAX_WIDTH)? & !1).max(2),
+            format,
+        })
+    }
+}
+
+/// Layout of a sprite sheet, for finding the frame at a timestamp
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SpriteIndex {
+    columns: u32,
+    rows: u32,
+    tile_width: u32,
+    tile_height: u32,
+    /// Timestamp (s) of each frame, left to right then top to bottom
+    times: Vec<f64>,
+}
+
+impl SpriteIndex {
+    async fn probe(
+        video_source: &str,
+        count: u32,
+        columns: u32,
+        width: u32,
+    ) -> Result<Self, String> {
+        let size = downscale::probe_video_size(video_source).await?;
+        let duration = ffprobe_cmd::probe_duration(video_source).await?;
+
+        let columns = columns.min(count);
+        let tile_height = (width as f64 * size.height as f64 / size.width as f64).round() as u32;
+        Ok(Self {
+            columns,
+            rows: count.div_ceil(columns),
+            tile_width: width,
+            tile_height: (tile_height & !1).max(2),
+            // The middle of each span, so the first frame isn't a black fade-in
+            times: (0..count)
+                .map(|i| duration * (i as f64 + 0.5) / count as f64)
+                .collect(),
+        })
+    }
+}
+
+async fn run_ffmpeg(command: &mut Command) -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to create the thumbnail: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+async fn render_frame(
+    video_source: &str,
+    time: f64,
+    width: u32,
+    format: ImageFormat,
+    path: &Path,
+) -> Result<(), String> {
+    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+    command
+        // Seeking before the input jumps to the keyframe before it instead of decoding up to it
+        .args([
+            "-v",
+            "error",
+            "-y",
+            "-ss",
+            &time.to_string(),
+            "-i",
+            video_source,
+        ])
+        .args(["-map", "0:v:0", "-frames:v", "1"])
+        .args(["-vf", &format!("scale={width}:-2")])
+        .args(format.encoder_args())
+        .args(["-f", "image2"])
+        .arg(path);
+    run_ffmpeg(&mut command).await
+}
+
+async fn render_sprite(
+    video_source: &str,
+    index: &SpriteIndex,
+    format: ImageFormat,
+    path: &Path,
+) -> Result<(), String> {
+    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+    command.args(["-v", "error", "-y"]);
+    // Every frame is its own input, so each is a fast seek instead of decoding the whole video
+    for time in &index.times {
+        command.args(["-ss", &time.to_string(), "-i", video_source]);
+    }
+
+    let mut graph = FilterGraph::default();
+    let frames: Vec<Stream> = (0..index.times.len())
+        .map(|input| {
+            graph.then(
+                &Stream::Input(format!("{input}:v:0")),
+                &[
+                    "trim=end_frame=1".into(),
+                    format!("scale={}:{}", index.tile_width, index.tile_height),
+                    "setsar=1".into(),
+                    "setpts=PTS-STARTPTS".into(),
+                ],
+                "v",
+            )
+        })
+        .collect();
+    let sprite = graph.stream("v");
+    graph.chain(
+        &frames.iter().collect::<Vec<_>>(),
+        &[
+            format!("concat=n={}:v=1:a=0", frames.len()),
+            format!("tile={}x{}", index.columns, index.rows),
+        ],
+        &[&sprite],
+    );
+
+    command
+        .args(["-filter_complex", &graph.to_string()])
+        .args(["-map", &sprite.map_arg(), "-frames:v", "1"])
+        .args(format.encoder_args())
+        .args(["-f", "image2"])
+        .arg(path);
+    run_ffmpeg(&mut command).await
+}
+
+// thumbnail:///video_source/frame?time=T&width=W&format=jpeg|webp
+// thumbnail:///video_source/sprite?count=N&columns=C&width=W&format=jpeg|webp
+// thumbnail:///video_source/sprite.json?count=N&columns=C&width=W
+pub async fn thumbnail_protocol(
+    req: http::Request<Vec<u8>>,
+) -> Result<http::Response<Vec<u8>>, String> {
+    let ThumbnailParams {
+        video_source,
+        kind,
+        width,
+        format,
+    } = ThumbnailParams::from_uri(req.uri())?;
+    let source = video_source.as_str();
+
+    let path = match kind {
+        ThumbnailKind::Frame { time } => {
+            let variant = format!("frame-{time:.3}-{width}");
+            cache::get_or_create(source, &variant, format.extension(), |path| async move {
+                render_frame(source, time, width, format, &path).await
+            })
+            .await?
+        }
+        ThumbnailKind::Sprite { count, columns } => {
+            let variant = format!("sprite-{count}-{columns}-{width}");
+            cache::get_or_create(source, &variant, format.extension(), |path| async move {
+                let index = SpriteIndex::probe(source, count, columns, width).await?;
+                render_sprite(source, &index, format, &path).await
+            })
+            .await?
+        }
+        ThumbnailKind::SpriteIndex { count, columns } => {
+            let index = SpriteIndex::probe(source, count, columns, width).await?;
+            return http::Response::builder()
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_vec(&index).map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string());
+        }
+    };
+
+    http::Response::builder()
+        .header("Content-Type", format.content_type())
+        .body(fs::read(path).map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())
+}
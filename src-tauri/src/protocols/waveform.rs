@@ -0,0 +1,349 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write as _},
+    path::Path,
+    process::Stdio,
+};
+
+use serde::Serialize;
+use tauri::http;
+use tokio::{io::AsyncReadExt as _, process::Command};
+
+use crate::{cache, FFMPEG_PATH};
+
+/// Rate (Hz) audio is decoded at to measure its peaks
+const SAMPLE_RATE: u32 = 8000;
+
+/// Peaks cached per second of audio, requests at lower resolutions are merged from them
+const PEAKS_PER_SECOND: u32 = 100;
+
+/// Most buckets a request can ask for
+const MAX_BUCKETS: usize = 100_000;
+
+/// Lowest and highest sample in a bucket
+type Peak = (i16, i16);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PayloadFormat {
+    Json,
+    /// Interleaved little-endian i16 min/max pairs
+    Binary,
+}
+
+#[derive(Debug)]
+struct WaveformParams {
+    video_source: String,
+    audio_track_index: u32,
+    buckets: usize,
+    format: PayloadFormat,
+}
+
+impl WaveformParams {
+    // /video_source/audio_track_index?buckets=N&format=json|binary
+    fn from_uri(uri: &http::Uri) -> Result<Self, String> {
+        let path = uri.path();
+        let (video_source, audio_track_index) = path
+            .strip_prefix('/')
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(|| format!("Missing delimiter in {path:?}"))?;
+
+        let video_source = urlencoding::decode(video_source)
+            .map_err(|e| format!("Invalid video source provided in {video_source:?}: {e}"))?;
+        let audio_track_index = audio_track_index
+            .parse()
+            .map_err(|e| format!("Invalid audio track index provided in {path:?}: {e}"))?;
+
+        let mut buckets = 1000;
+        let mut format = PayloadFormat::Json;
+        for pair in uri.query().unwrap_or_default().split('&') {
+            match pair.split_once('=') {
+                Some(("buckets", value)) => {
+                    buckets = value
+                        .parse()
+                        .ok()
+                        .filter(|buckets| (1..=MAX_BUCKETS).contains(buckets))
+                        .ok_or_else(|| {
+                            format!("Buckets must be between 1 and {MAX_BUCKETS}, got {value}")
+                        })?;
+                }
+                Some(("format", "json")) => format = PayloadFormat::Json,
+                Some(("format", "binary")) => format = PayloadFormat::Binary,
+                Some(("format", value)) => return Err(format!("Unsupported format: {value}")),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            video_source: video_source.into_owned(),
+            audio_track_index,
+            buckets,
+            format,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WaveformJson {
+    /// Seconds of audio the peaks cover
+    duration: f64,
+    /// Lowest sample of each bucket, from -1 to 1
+    min: Vec<f32>,
+    /// Highest sample of each bucket, from -1 to 1
+    max: Vec<f32>,
+}
+
+// Folds samples into peaks, each covering a fixed number of them
+struct PeakAccumulator {
+    samples_per_peak: usize,
+    count: usize,
+    current: Peak,
+    peaks: Vec<Peak>,
+}
+
+impl PeakAccumulator {
+    fn new(samples_per_peak: usize) -> Self {
+        Self {
+            samples_per_peak,
+            count: 0,
+            current: (i16::MAX, i16::MIN),
+            peaks: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sample: i16) {
+        self.current = (self.current.0.min(sample), self.current.1.max(sample));
+        self.count += 1;
+        if self.count == self.samples_per_peak {
+            self.peaks.push(self.current);
+            self.current = (i16::MAX, i16::MIN);
+            self.count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<Peak> {
+        if self.count > 0 {
+            self.peaks.push(self.current);
+        }
+        self.peaks
+    }
+}
+
+// Decodes the track to mono PCM and measures its peaks into the cache file at `path`
+async fn measure_peaks(
+    video_source: &str,
+    audio_track_index: u32,
+    path: &Path,
+) -> Result<(), String> {
+    let mut command = Command::new(FFMPEG_PATH.get().unwrap());
+    command.args([
+        "-v",
+        "error",
+        "-i",
+        video_source,
+        "-map",
+        &format!("0:{audio_track_index}"),
+        "-ac",
+        "1",
+        "-ar",
+        &SAMPLE_RATE.to_string(),
+        "-c:a",
+        "pcm_s16le",
+        "-f",
+        "s16le",
+        "pipe:1",
+    ]);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Stderr is read alongside stdout, since FFMPEG stops writing samples once a full stderr pipe blocks it
+    let mut stderr = String::new();
+    let mut child_stderr = child.stderr.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut accumulator = PeakAccumulator::new((SAMPLE_RATE / PEAKS_PER_SECOND) as usize);
+    let read_samples = async {
+        let mut buffer = vec![0; 64 * 1024];
+        // Byte of a sample split across two reads
+        let mut leftover: Option<u8> = None;
+        loop {
+            let read = stdout.read(&mut buffer).await.map_err(|e| e.to_string())?;
+            if read == 0 {
+                break;
+            }
+
+            let mut bytes = &buffer[..read];
+            if let Some(low) = leftover.take() {
+                accumulator.push(i16::from_le_bytes([low, bytes[0]]));
+                bytes = &bytes[1..];
+            }
+            let mut samples = bytes.chunks_exact(2);
+            for sample in &mut samples {
+                accumulator.push(i16::from_le_bytes([sample[0], sample[1]]));
+            }
+            leftover = samples.remainder().first().copied();
+        }
+        Ok::<_, String>(())
+    };
+    let (samples_result, stderr_result) =
+        tokio::join!(read_samples, child_stderr.read_to_string(&mut stderr));
+    samples_result?;
+    stderr_result.map_err(|e| e.to_string())?;
+
+    let status = child.wait().await.map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!(
+            "Failed to decode audio track {audio_track_index}: {}",
+            stderr.trim()
+        ));
+    }
+
+    let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    for (min, max) in accumulator.finish() {
+        file.write_all(&min.to_le_bytes())
+            .and_then(|_| file.write_all(&max.to_le_bytes()))
+            .map_err(|e| e.to_string())?;
+    }
+    file.flush().map_err(|e| e.to_string())
+}
+
+fn read_peaks(path: &Path) -> Result<Vec<Peak>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|peak| {
+            (
+                i16::from_le_bytes([peak[0], peak[1]]),
+                i16::from_le_bytes([peak[2], peak[3]]),
+            )
+        })
+        .collect())
+}
+
+// Merges the cached peaks into the requested number of buckets
+fn resample(peaks: &[Peak], buckets: usize) -> Vec<Peak> {
+    (0..buckets)
+        .map(|bucket| {
+            let start = bucket * peaks.len() / buckets;
+            let end = ((bucket + 1) * peaks.len() / buckets).max(start + 1);
+            peaks
+                .get(start..end.min(peaks.len()))
+                .filter(|peaks| !peaks.is_empty())
+                .map(|peaks| {
+                    peaks.iter().fold((i16::MAX, i16::MIN), |(min, max), peak| {
+                        (min.min(peak.0), max.max(peak.1))
+                    })
+                })
+                .unwrap_or((0, 0))
+        })
+        .collect()
+}
+
+// waveform:///video_source/audio_track_index?buckets=N&format=json|binary
+pub async fn waveform_protocol(
+    req: http::Request<Vec<u8>>,
+) -> Result<http::Response<Vec<u8>>, String> {
+    let WaveformParams {
+        video_source,
+        audio_track_index,
+        buckets,
+        format,
+    } = WaveformParams::from_uri(req.uri())?;
+
+    let variant = format!("waveform-{audio_track_index}-{SAMPLE_RATE}-{PEAKS_PER_SECOND}");
+    let source = video_source.as_str();
+    let path = cache::get_or_create(source, &variant, "peaks", |path| async move {
+        measure_peaks(source, audio_track_index, &path).await
+    })
+    .await?;
+
+    let peaks = read_peaks(&path)?;
+    let resampled = resample(&peaks, buckets);
+
+    let (content_type, body) = match format {
+        PayloadFormat::Json => {
+            let scale = |sample: i16| sample as f32 / -(i16::MIN as f32);
+            let waveform = WaveformJson {
+                duration: peaks.len() as f64 / PEAKS_PER_SECOND as f64,
+                min: resampled.iter().map(|peak| scale(peak.0)).collect(),
+                max: resampled.iter().map(|peak| scale(peak.1)).collect(),
+            };
+            (
+                "application/json",
+                serde_json::to_vec(&waveform).map_err(|e| e.to_string())?,
+            )
+        }
+        PayloadFormat::Binary => (
+            "application/octet-stream",
+            resampled
+                .iter()
+                .flat_map(|(min, max)| [min.to_le_bytes(), max.to_le_bytes()])
+                .flatten()
+                .collect(),
+        ),
+    };
+
+    http::Response::builder()
+        .header("Content-Type", content_type)
+        .body(body)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peak_accumulator_keeps_the_extremes_of_each_peak() {
+        let mut accumulator = PeakAccumulator::new(3);
+        for sample in [5, -2, 3, 100, -100, 0] {
+            accumulator.push(sample);
+        }
+        assert_eq!(accumulator.finish(), [(-2, 5), (-100, 100)]);
+    }
+
+    #[test]
+    fn peak_accumulator_finishes_a_partial_peak() {
+        let mut accumulator = PeakAccumulator::new(4);
+        for sample in [1, 2, 3, 4, -7] {
+            accumulator.push(sample);
+        }
+        assert_eq!(accumulator.finish(), [(1, 4), (-7, -7)]);
+    }
+
+    #[test]
+    fn peak_accumulator_without_samples_has_no_peaks() {
+        assert!(PeakAccumulator::new(80).finish().is_empty());
+    }
+
+    #[test]
+    fn resample_merges_peaks_into_fewer_buckets() {
+        let peaks = [(-1, 1), (-5, 2), (-2, 8), (0, 3)];
+        assert_eq!(resample(&peaks, 2), [(-5, 2), (-2, 8)]);
+        assert_eq!(resample(&peaks, 1), [(-5, 8)]);
+    }
+
+    #[test]
+    fn resample_spreads_uneven_peaks_over_the_buckets() {
+        let peaks = [(-1, 1), (-2, 2), (-3, 3)];
+        assert_eq!(resample(&peaks, 2), [(-1, 1), (-3, 3)]);
+    }
+
+    #[test]
+    fn resample_repeats_peaks_for_more_buckets() {
+        let peaks = [(-1, 1), (-2, 2)];
+        assert_eq!(resample(&peaks, 4), [(-1, 1), (-1, 1), (-2, 2), (-2, 2)]);
+    }
+
+    #[test]
+    fn resample_without_peaks_is_silent() {
+        assert_eq!(resample(&[], 3), [(0, 0), (0, 0), (0, 0)]);
+    }
+}
@@ -1,7 +1,7 @@
 import { Event, UnlistenFn, listen } from "@tauri-apps/api/event";
 
 export type MenubarBtnOption = (typeof MENUBAR_BTN_OPTS)[number];
-export const MENUBAR_BTN_OPTS = ["zoom_in", "zoom_out", "prefs", "new_proj"] as const;
+export const MENUBAR_BTN_OPTS = ["zoom_in", "zoom_out", "prefs", "clear_cache", "new_proj"] as const;
 
 class Menubar {
   private unlisteners: UnlistenFn[] = new Array(MENUBAR_BTN_OPTS.length);
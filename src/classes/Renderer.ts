@@ -1,11 +1,9 @@
-import { invoke } from "@tauri-apps/api/core";
-import { RawProgress, RenderMeta, RenderSettings, RenderSizeLimit } from "../../types";
-import { remove, stat } from "@tauri-apps/plugin-fs";
-import { Event, UnlistenFn, listen } from "@tauri-apps/api/event";
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { LoudnessMeasurement, RenderMeta, RenderProgress, RenderSettings, RenderSizeLimit } from "../../types";
 import { VideoCodecs } from "../components/export_panel/Codecs";
 import { Accessor, Setter, createSignal } from "solid-js";
 import { SetStoreFunction, createStore } from "solid-js/store";
-import { minmax, round } from "../util";
+import { round } from "../util";
 
 // Render states
 export enum RenderState {
@@ -24,6 +22,10 @@ type ProgressStore = {
   fps: number;
   eta: null | Date;
   speed: number;
+  pass: number | null;
+  downscaledTo: string | null; // Resolution and frame rate picked to fit the size limit
+  measuringLoudness: boolean;
+  loudness: LoudnessMeasurement[]; // Loudness of the audio before it was normalised
   state: RenderState;
   doneCurrent: boolean;
 };
@@ -36,7 +38,7 @@ type Attempt = {
 type Attempts = Attempt[];
 
 export default class Renderer {
-  private settings: RenderSettings & { codecRateControl: string[] };
+  private settings: RenderSettings & { codecRateControl: readonly string[] };
   private sizeLimit: RenderSizeLimit | null;
   private meta: RenderMeta;
 
@@ -48,71 +50,16 @@ export default class Renderer {
   private setCurrentAttempt: Setter<number>;
 
   readonly useCurrentAttempt: Accessor<boolean>;
-  readonly setUseCurrentAttempt: Setter<boolean>;
+  private setUseCurrentAttempt: Setter<boolean>;
 
   readonly lastAttempts: Attempts;
   private setLastAttempts: SetStoreFunction<Attempts>;
 
-  // Define defaults for internal memory objects that persist throughout the entire renderer lifespan
-  private bestAttempt: {
-    size: number; // Bytes
-    minBitrate: number | null;
-    targetBitrate: number | null;
-    maxBitrate: number;
-  } = {
-    size: 0,
-    minBitrate: null,
-    targetBitrate: null,
-    maxBitrate: Infinity,
-  };
-  private memory: {
-    lastPercentDiff: number;
-    maxSetBitrate: number;
-    minSetBitrate: number | null;
-  } = {
-    lastPercentDiff: 0.1,
-    maxSetBitrate: Infinity,
-    minSetBitrate: null,
-  };
-
   // Current render ID by Tauri backend
   private currentRenderId: number | undefined;
 
-  // Function used on cleanup to un-listen to Tauri events for render progress
-  private progressUnlistener: UnlistenFn | undefined;
-
-  private static readonly ERROR_PREFIX = "error:";
-
-  private static generateRateControlCmd(settings: RenderSettings) {
-    const rateControlCommand: string[] = [];
-
-    // Map templating strings to their property names
-    const replacementMap = new Map([
-      ["TARGET_BITRATE", "targetBitrate"],
-      ["MIN_BITRATE", "minBitrate"],
-      ["MAX_BITRATE", "maxBitrate"],
-      ["CRF_VALUE", "crfValue"],
-      ["BUF_SIZE", "bufSize"],
-    ]);
-
-    // Replace string templates with respective value
-    for (const arg of VideoCodecs[settings.vCodecName].rateControl[settings.rateControl]!) {
-      const openingBracketIndex = arg.indexOf("{");
-
-      if (openingBracketIndex != -1) {
-        // Current argument has template to fill, insert value
-        const template = arg.slice(openingBracketIndex + 1, arg.indexOf("}"));
-
-        if (replacementMap.has(template))
-          rateControlCommand.push(arg.replace(`{${template}}`, settings[replacementMap.get(template)! as keyof RenderSettings].toString()));
-      } else {
-        // Current argument has no template to fill, push argument as-is
-        rateControlCommand.push(arg);
-      }
-    }
-
-    return rateControlCommand;
-  }
+  // Channel the backend sends progress of this render's task to
+  private progressChannel: Channel<RenderProgress> | undefined;
 
   constructor(settings: RenderSettings, sizeLimit: RenderSizeLimit | null, meta: RenderMeta) {
     // Initialize default states
@@ -124,26 +71,18 @@ export default class Renderer {
       fps: 0,
       eta: null,
       speed: 1,
+      pass: null,
+      downscaledTo: null,
+      measuringLoudness: false,
+      loudness: [],
       doneCurrent: false,
       state: RenderState.LOADING,
     });
     [this.useCurrentAttempt, this.setUseCurrentAttempt] = createSignal(false);
     [this.lastAttempts, this.setLastAttempts] = createStore<Attempts>([]);
 
-    // If a size limit is enabled, adjust settings to include this
-    if (sizeLimit != null) {
-      const duration = settings.trimEnd - settings.trimStart;
-      // TODO: subtract audio bitrate from calculations
-      // Convert max size (MB) to megabits (Mb), then divide by duration (s) to get Mb/s, then multiply by 1000 to get Kb/s
-      const theoreticalConstantBitrateKbps = ((sizeLimit.maxSize * 8) / duration) * 1000;
-
-      settings.targetBitrate = theoreticalConstantBitrateKbps;
-      settings.maxBitrate = theoreticalConstantBitrateKbps;
-      settings.minBitrate = 0;
-    }
-
-    // Set settings with FFMPEG arguments for rate control
-    this.settings = { ...settings, codecRateControl: Renderer.generateRateControlCmd(settings) };
+    // Pass the rate control templates for FFMPEG, the backend fills in the bitrates
+    this.settings = { ...settings, codecRateControl: VideoCodecs[settings.vCodecName].rateControl[settings.rateControl]! };
     this.sizeLimit = sizeLimit;
     this.meta = meta;
   }
@@ -157,56 +96,84 @@ export default class Renderer {
     return this.settings.outputFilepath;
   }
 
-  // Mandatory-called async initialization function to listen for progress events from Tauri and FFMPEG
+  // Mandatory-called async initialization function to receive progress events from Tauri and FFMPEG
   async init() {
-    this.progressUnlistener = await listen<string>("export_progress", (data) => this.handleProgress(data));
+    this.progressChannel = new Channel<RenderProgress>();
+    this.progressChannel.onmessage = (event) => this.handleProgress(event);
   }
 
-  handleProgress(data: Event<string>) {
-    const lines = data.payload;
-
+  handleProgress(event: RenderProgress) {
     const newProgress: ProgressStore = Object.assign({}, this.progress); // Clone current progress object
 
-    // Check if lines start with the error prefix meaning there is an error
-    if (lines.startsWith(Renderer.ERROR_PREFIX)) {
-      newProgress.state = RenderState.ERRORED;
-      newProgress.errorMsg = lines.slice(Renderer.ERROR_PREFIX.length);
-    } else {
-      // If there is no error, parse and set progress value normally
-      lines.split("\n").forEach((property) => {
-        const [name, value] = property.split("=") as [keyof RawProgress, string];
-        const validValue = value !== "N/A";
-
-        if (!validValue) return;
-
-        switch (name) {
-          case "out_time_us": {
-            // FFMPEG gives both ms and us, however the ms reading is identical to us due to a bug in FFMPEG,
-            // so use the us time and convert to ms instead
-            newProgress.currentTimeMs = Number(value) / 1000; // Convert value (us) to milliseconds
-            newProgress.percentage = newProgress.currentTimeMs / 1000 / this.meta.totalDuration; // Convert milliseconds to seconds, then to percentage
-            break;
-          }
-          case "speed": {
-            // Speed is given by FFMPEG as a multiplier, for example: "1.76x"
-            newProgress.speed = parseFloat(value);
-            // Convert duration to milliseconds, subtracted by the position (in ms) the render is currently at, divided by the current speed multiplier
-            // to get the remaining duration, and convert to a date
-            newProgress.eta = new Date(Date.now() + (this.meta.totalDuration * 1000 - newProgress.currentTimeMs) / newProgress.speed);
-            break;
-          }
-          case "fps": {
-            newProgress.fps = parseFloat(value);
-            break;
-          }
-          case "progress": {
-            // Update render states, set progress to 100% (1) if done as FFMPEG does not emit an event for this
-            newProgress.doneCurrent = value === "end" ? true : false;
-            if (newProgress.state === RenderState.LOADING) newProgress.state = RenderState.RENDERING;
-            if (newProgress.doneCurrent) newProgress.percentage = 1;
-          }
+    switch (event.type) {
+      case "trimSnapped": {
+        // Stream copies start at the keyframe before the trim, so the output is longer than requested
+        this.meta.totalDuration = event.ranges.reduce((total, range) => total + range.end - range.start, 0);
+        return;
+      }
+      case "downscaled": {
+        newProgress.downscaledTo = `${event.width}x${event.height} @ ${round(event.fps)} fps`;
+        break;
+      }
+      case "measuringLoudness": {
+        newProgress.state = RenderState.LOADING;
+        newProgress.measuringLoudness = true;
+        newProgress.percentage = 0;
+        newProgress.eta = null;
+        break;
+      }
+      case "started": {
+        // A new FFMPEG process (attempt or pass) has started
+        this.setCurrentAttempt(event.attempt);
+        newProgress.measuringLoudness = false;
+        newProgress.state = RenderState.LOADING;
+        newProgress.pass = event.pass;
+        newProgress.percentage = 0;
+        newProgress.eta = null;
+        break;
+      }
+      case "progress": {
+        newProgress.state = RenderState.RENDERING;
+
+        if (event.outTimeUs != null) {
+          // FFMPEG gives both ms and us, however the ms reading is identical to us due to a bug in FFMPEG,
+          // so use the us time and convert to ms instead
+          newProgress.currentTimeMs = event.outTimeUs / 1000; // Convert value (us) to milliseconds
+          newProgress.percentage = newProgress.currentTimeMs / 1000 / this.meta.totalDuration; // Convert milliseconds to seconds, then to percentage
         }
-      });
+        if (event.speed != null && event.speed > 0) {
+          newProgress.speed = event.speed;
+          // Convert duration to milliseconds, subtracted by the position (in ms) the render is currently at, divided by the current speed multiplier
+          // to get the remaining duration, and convert to a date
+          newProgress.eta = new Date(Date.now() + (this.meta.totalDuration * 1000 - newProgress.currentTimeMs) / newProgress.speed);
+        }
+        if (event.fps != null) newProgress.fps = event.fps;
+        break;
+      }
+      case "attemptFinished": {
+        newProgress.state = RenderState.VALIDATING;
+        // Append another attempt to the previous attempts
+        this.setLastAttempts(this.lastAttempts.length, {
+          bitrate: event.targetBitrate,
+          size: round(event.size / 1e6), // Convert bytes to MB
+        });
+        break;
+      }
+      case "finished": {
+        newProgress.doneCurrent = true;
+        newProgress.loudness = event.loudness;
+        newProgress.percentage = 1;
+        break;
+      }
+      case "failed": {
+        newProgress.state = RenderState.ERRORED;
+        newProgress.errorMsg = `FFMPEG exited with ${event.exitStatus ?? "an error"}\n${event.stderrTail}`;
+        break;
+      }
+      case "cancelled": {
+        this.cleanup();
+        return;
+      }
     }
 
     this.setProgress(newProgress);
@@ -217,19 +184,26 @@ export default class Renderer {
       return;
     }
 
-    // If current render is done, start post-render tasks
-    if (newProgress.doneCurrent) this.postRender();
+    // If the render is done, start post-render tasks
+    if (event.type === "finished") this.postRender(event.outputSize);
   }
 
   async render(): Promise<void> {
     this.setProgress("state", RenderState.LOADING);
     this.setProgress("percentage", 0);
-    this.setCurrentAttempt((prev) => ++prev);
 
     try {
-      // Send render request to Tauri, which will return a render ID
-      const id = await invoke<number>("start_render", this.settings);
-      this.currentRenderId = id;
+      // Send render request to Tauri, which will return a render ID.
+      // With a size limit, the backend renders as many attempts as needed to fit the file under it
+      if (this.sizeLimit == null) {
+        this.currentRenderId = await invoke<number>("start_render", { settings: this.settings, onProgress: this.progressChannel });
+      } else {
+        this.currentRenderId = await invoke<number>("start_size_limited_render", {
+          settings: this.settings,
+          sizeLimit: this.sizeLimit,
+          onProgress: this.progressChannel,
+        });
+      }
     } catch (err) {
       // Stop the render if there is an error
       alert(err);
@@ -237,113 +211,21 @@ export default class Renderer {
     }
   }
 
-  // This function adjusts the current settings to attempt to reach the target size limit,
-  // returning whether or not adjusting was needed
-  private adjustSettings(resultantSize: number, finalAttempt: boolean): boolean {
-    const targetSizeBytes = this.sizeLimit!.maxSize * 1e6; // Convert MB to bytes
-    // > 0 : over size limit
-    // < 0 : under size limit
-    // This is achieved through the subtraction of 1
-    const percentDiff = resultantSize / targetSizeBytes - 1;
-
-    // If percent difference is less than or equal to zero and the inverted percent difference (inversion is needed due to prior subtraction by 1)
-    // is less than the retry threshold percentage, stop adjusting
-    if (percentDiff <= 0 && -percentDiff < this.sizeLimit!.retryThreshold) return false;
-
-    // Constrain max allowed bitrate if the resultant size is bigger than the target size
-    if (resultantSize > targetSizeBytes) {
-      this.memory.maxSetBitrate = Math.min(this.memory.maxSetBitrate, this.settings.maxBitrate);
-    }
-    // Constrain min allowed bitrate if the resultant size is smaller than the target size
-    if (resultantSize < targetSizeBytes) this.memory.minSetBitrate = this.settings.targetBitrate;
-
-    // Use square root curve to produce multiplier
-    const multiplier = Math.sqrt(42 * Math.abs(Math.abs(this.memory.lastPercentDiff) - Math.abs(percentDiff))) + 1;
-
-    // Attempt to adjust settings if it is not the final attempt
-    if (!finalAttempt) {
-      // If max and min bitrate bounds are set and not default values
-      if (this.memory.maxSetBitrate !== Infinity && this.memory.minSetBitrate !== null) {
-        this.settings.targetBitrate = minmax(
-          this.memory.minSetBitrate,
-          this.settings.targetBitrate - (this.memory.maxSetBitrate - this.memory.minSetBitrate) * percentDiff,
-          this.memory.maxSetBitrate
-        );
-        this.settings.maxBitrate = minmax(
-          this.memory.minSetBitrate,
-          this.settings.maxBitrate - (this.memory.maxSetBitrate - this.memory.minSetBitrate) * percentDiff,
-          this.memory.maxSetBitrate
-        );
-      } else {
-        this.settings.targetBitrate = minmax(
-          this.memory.minSetBitrate || 0.01,
-          this.settings.targetBitrate - this.settings.targetBitrate * percentDiff * multiplier,
-          this.memory.maxSetBitrate
-        );
-        this.settings.maxBitrate = minmax(
-          this.memory.minSetBitrate || 0.01,
-          this.settings.maxBitrate - this.settings.maxBitrate * percentDiff * multiplier,
-          this.memory.maxSetBitrate
-        );
-      }
-      this.memory.lastPercentDiff = percentDiff;
-    } else {
-      // Give up on adjusting as it is the final attempt and we have ran out of attempts,
-      // use the best found settings so far instead
-      this.settings.maxBitrate = this.bestAttempt.maxBitrate == Infinity ? this.bestAttempt.targetBitrate! : this.bestAttempt.maxBitrate;
-      this.settings.targetBitrate = this.bestAttempt.targetBitrate!;
-      this.settings.minBitrate = this.bestAttempt.minBitrate || 0.01;
-    }
-
-    // Regenerate the command to be passed to FFMPEG with the current adjusted settings
-    this.settings.codecRateControl = Renderer.generateRateControlCmd(this.settings);
+  async toggleUseCurrentAttempt() {
+    const useCurrent = !this.useCurrentAttempt();
+    this.setUseCurrentAttempt(useCurrent);
 
-    return true;
+    // Tell the backend to stop searching once the current attempt is done
+    await invoke<boolean>("set_accept_current_attempt", { taskId: this.currentRenderId, accept: useCurrent });
   }
 
-  async postRender() {
-    // Retrieve stats for file
-    const file = await stat(this.settings.outputFilepath);
-
-    // Append another attempt to the previous attempts
-    this.setLastAttempts(this.lastAttempts.length, {
-      bitrate: this.settings.targetBitrate,
-      size: round(file.size / 1e6), // Convert bytes to MB
-    });
-
-    // If there is a size limit specified and the user did not select they want to use the current attempt
-    if (this.sizeLimit != null && !this.useCurrentAttempt()) {
-      this.setProgress("state", RenderState.VALIDATING);
-
-      const maxSizeBytes = this.sizeLimit.maxSize * 1e6; // Convert MB to bytes
-
-      // Set best attempt to current settings if:
-      // there is no best attempt yet
-      // the resultant file is less than the target size and if the current target bitrate is larger than the last best attempt bitrate or if last best attempt was larger than the target size
-      // the resultant file is larger than the target size and is less than the last best attempt's size
-      if (
-        this.bestAttempt.targetBitrate == null ||
-        (file.size <= maxSizeBytes && (this.settings.targetBitrate > this.bestAttempt.targetBitrate || this.bestAttempt.size > maxSizeBytes)) ||
-        (file.size >= maxSizeBytes && file.size < this.bestAttempt.size)
-      ) {
-        this.bestAttempt.size = file.size;
-        this.bestAttempt.targetBitrate = this.settings.targetBitrate;
-        this.bestAttempt.maxBitrate = this.settings.maxBitrate;
-        this.bestAttempt.minBitrate = this.settings.minBitrate;
-      }
-
-      // Call to adjust settings, passing in if the nxt attempt will be the last (final)
-      const adjusted = this.adjustSettings(file.size, this.currentAttempt() == this.maxAttempts - 1);
-
-      // If adjustments were made and the current attempt is less than the max allowed attempts,
-      // remove the old file and rerender
-      if (adjusted && this.currentAttempt() < this.sizeLimit.maxAttempts) {
-        await remove(this.settings.outputFilepath);
-
-        this.render();
-
-        return;
-      }
+  postRender(outputSize: number) {
+    // Attempts with a size limit are logged by the backend, otherwise log the resultant file
+    if (this.sizeLimit == null) {
+      this.setLastAttempts(this.lastAttempts.length, {
+        bitrate: this.settings.rateControl === "crf" ? null : this.settings.targetBitrate,
+        size: round(outputSize / 1e6), // Convert bytes to MB
+      });
     }
 
     // Render is complete, cleanup
@@ -358,6 +240,6 @@ export default class Renderer {
   cleanup() {
     // Render done, errored or not, cleanup
     if (this.progress.state !== RenderState.ERRORED) this.setProgress("state", RenderState.FINISHED);
-    this.progressUnlistener!();
+    this.progressChannel!.onmessage = () => {};
   }
 }
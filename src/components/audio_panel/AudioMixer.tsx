@@ -4,16 +4,17 @@ import Panel from "../panel/Panel";
 
 import { useAppContext } from "../../contexts/AppContext";
 import { usePlayerContext } from "../../contexts/PlayerContext";
-import { FfprobeAudioStream } from "../../../types";
 
 import styles from "./AudioMixer.module.css";
 import panelStyles from "../panel/PanelCommon.module.css";
 
 export function createAudioAnalyser(audioContext: AudioContext, source: HTMLMediaElement) {
   const mediaSource = audioContext.createMediaElementSource(source);
+  const pannerNode = audioContext.createStereoPanner();
   const analyserNode = audioContext.createAnalyser();
 
-  mediaSource.connect(analyserNode);
+  mediaSource.connect(pannerNode);
+  pannerNode.connect(analyserNode);
   analyserNode.connect(audioContext.destination);
 
   const pcmData = new Float32Array(analyserNode.fftSize);
@@ -26,17 +27,14 @@ export function createAudioAnalyser(audioContext: AudioContext, source: HTMLMedi
     return Math.sqrt(sumSquares / pcmData.length);
   }
 
-  return { computeAmplitude, source: mediaSource };
+  return { computeAmplitude, source: mediaSource, panner: pannerNode };
 }
 
 const MAX_AUDIO_DIFF = 0.1;
 
-type AudioAbortion = { abortController: AbortController; id: number };
-
 export default function AudioMixer() {
   const [{ videoFile, mediaData }] = useAppContext();
   const [{ playing, currentTime, audioTracks, audioContext }, { setAudioTracks }] = usePlayerContext();
-  const [audioRequests, setAudioRequests] = createSignal<AudioAbortion[]>([]);
   const [audioMeters, setAudioMeters] = createSignal<number[]>([]);
 
   function updateAudioTracks() {
@@ -72,6 +70,8 @@ export default function AudioMixer() {
     // Mute respective track when triggered
     audioTracks.forEach((stream) => {
       stream.sourceElement.muted = stream.muted;
+      stream.sourceElement.volume = stream.volume;
+      stream.pannerNode.pan.value = stream.pan;
     });
   });
 
@@ -94,53 +94,32 @@ export default function AudioMixer() {
     const video = videoFile();
     if (data == null || video == null) return;
 
-    (data.streams.filter((stream) => stream.codec_type === "audio") as FfprobeAudioStream[]).forEach(async (stream, i) => {
+    data.streams.filter((stream) => stream.codecType === "audio").forEach((stream, i) => {
       if (i === 0) return setAudioTracks(0, "trackIndex", stream.index);
 
-      const abortController = new AbortController();
-      let id = 1;
-      setAudioRequests((prev) => {
-        id = prev.length;
-        return [...prev, { abortController, id }];
-      });
-
-      const blob = await (
-        await fetch(`${location.protocol}//extract-audio.${location.hostname}/${encodeURIComponent(video)}/${stream.index}`, {
-          signal: abortController.signal,
-        })
-      ).blob();
-
-      const audio = new Audio(URL.createObjectURL(blob));
+      // Loaded by the audio element in ranges, so playback can start while the track is still being extracted
+      const audio = new Audio(`${location.protocol}//extract-audio.${location.hostname}/${encodeURIComponent(video)}/${stream.index}`);
       audio.crossOrigin = "anonymous";
 
-      const { computeAmplitude, source } = createAudioAnalyser(audioContext, audio);
+      const { computeAmplitude, source, panner } = createAudioAnalyser(audioContext, audio);
 
       setAudioTracks(i, {
         trackIndex: stream.index,
         muted: false,
+        volume: 1,
+        pan: 0,
         getCurrentAmplitude: computeAmplitude,
         sourceElement: audio,
         sourceNode: source,
+        pannerNode: panner,
       });
-
-      removeAudioReqAborter(id);
     });
   });
 
-  function removeAudioReqAborter(id: number) {
-    setAudioRequests((requests) => requests.filter((req) => req.id === id));
-  }
-
-  function abortRequest(request: AudioAbortion) {
-    request.abortController.abort();
-    removeAudioReqAborter(request.id);
-  }
-
   function cleanup() {
-    audioRequests().forEach((req) => abortRequest(req));
-
     audioTracks.slice(1).forEach((track) => {
       track.sourceNode.disconnect();
+      track.pannerNode.disconnect();
 
       // Remove audio: https://stackoverflow.com/questions/3258587/how-to-properly-unload-destroy-a-video-element
       // This will consistently work only if the audio has been loaded for longer than a few seconds
@@ -150,8 +129,6 @@ export default function AudioMixer() {
       track.sourceElement.src = "";
       track.sourceElement.srcObject = null;
       track.sourceElement.remove();
-
-      URL.revokeObjectURL(track.sourceElement.src);
     });
 
     setAudioTracks([audioTracks[0]]);
@@ -182,7 +159,28 @@ export default function AudioMixer() {
                   <button class={styles.audio_mixer__btn} onClick={() => setAudioTracks(i(), "muted", !stream.muted)}>
                     <i class={"fa-sharp fa-solid " + (stream.muted ? "fa-volume-slash" : "fa-volume")}></i>
                   </button>
+                  <input
+                    type="range"
+                    aria-label={`Track ${i() + 1} volume`}
+                    min="0"
+                    max="1"
+                    step="0.01"
+                    value={stream.volume}
+                    onInput={(e) => setAudioTracks(i(), "volume", e.target.valueAsNumber)}
+                  />
                 </div>
+                <input
+                  type="range"
+                  class={styles.audio_mixer__pan}
+                  aria-label={`Track ${i() + 1} pan`}
+                  title="Pan, double click to center"
+                  min="-1"
+                  max="1"
+                  step="0.01"
+                  value={stream.pan}
+                  onInput={(e) => setAudioTracks(i(), "pan", e.target.valueAsNumber)}
+                  onDblClick={() => setAudioTracks(i(), "pan", 0)}
+                />
                 <div class={styles.audio_mixer__visualizer} role="meter" style={`--silence: ${100 - audioMeters()[i()] * 100}%`}></div>
               </li>
             )}
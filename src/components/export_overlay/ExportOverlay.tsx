@@ -42,7 +42,14 @@ export default function ExportOverlay() {
             Attempt {renderData.renderer?.currentAttempt() ?? ""}/{renderData.renderer?.maxAttempts || ""}
           </p>
         </Show>
-        <p>{stateMap.get(progress()?.state || RenderState.LOADING)}</p>
+        <p>
+          {stateMap.get(progress()?.state || RenderState.LOADING)}
+          <Show when={progress()?.pass != null}> (pass {progress()?.pass}/2)</Show>
+          <Show when={progress()?.measuringLoudness}> (measuring loudness)</Show>
+        </p>
+        <Show when={progress()?.downscaledTo != null}>
+          <p>Scaled down to {progress()?.downscaledTo} to fit the size limit</p>
+        </Show>
 
         <LoadingBar
           name="Export progress"
@@ -116,7 +123,7 @@ export default function ExportOverlay() {
             }
           >
             <Show when={hasMultipleAttempts()}>
-              <button class={styles.export_btn} onClick={() => renderData.renderer?.setUseCurrentAttempt((prev) => !prev)}>
+              <button class={styles.export_btn} onClick={() => renderData.renderer?.toggleUseCurrentAttempt()}>
                 {!renderData.renderer?.useCurrentAttempt() ? "Accept Current" : "Auto Select"}
               </button>
             </Show>
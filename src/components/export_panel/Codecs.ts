@@ -98,6 +98,9 @@ export const VideoCodecs = {
   },
 } as const;
 
+// Encoders supporting two-pass encoding, which lands much closer to a target bitrate
+export const TwoPassEncoders: readonly string[] = ["libx264", "libx265", "libvpx-vp9", "libaom-av1"];
+
 export type VendorSuffix = keyof typeof VideoCodecHwVendorSuffixes;
 export const VideoCodecHwVendorSuffixes = {
   nvenc: "Nvidia",
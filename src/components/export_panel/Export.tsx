@@ -1,5 +1,6 @@
 import { For, Show, createEffect, onMount } from "solid-js";
 import { useAppContext } from "../../contexts/AppContext";
+import { usePlayerContext } from "../../contexts/PlayerContext";
 import Panel from "../panel/Panel";
 
 import panelStyles from "../panel/PanelCommon.module.css";
@@ -9,8 +10,8 @@ import { createStore } from "solid-js/store";
 import { path } from "@tauri-apps/api";
 import { invoke } from "@tauri-apps/api/core";
 
-import { ExportInfo, RateControlType, RenderInfo } from "../../../types";
-import { AudioCodec, AudioCodecs, VendorSuffix, VideoCodec, VideoCodecHwVendorSuffixes, VideoCodecs } from "./Codecs";
+import { ExportInfo, RateControlType, RenderInfo, RenderMode, RenderSizeLimit, ScaleAlgorithm } from "../../../types";
+import { AudioCodec, AudioCodecs, TwoPassEncoders, VendorSuffix, VideoCodec, VideoCodecHwVendorSuffixes, VideoCodecs } from "./Codecs";
 import { exists } from "@tauri-apps/plugin-fs";
 import { round } from "../../util";
 
@@ -20,7 +21,8 @@ type Codec<T> = {
 };
 
 export default function Export() {
-  const [{ mediaData, videoFile }, {}, { render }] = useAppContext();
+  const [{ mediaData, videoFile, trim, trimRanges }, {}, { render }] = useAppContext();
+  const [{ audioTracks }] = usePlayerContext();
 
   const [exportInfo, setExportInfo] = createStore<ExportInfo>({
     filename: null,
@@ -29,20 +31,34 @@ export default function Export() {
     absolutePath: null,
     width: null,
     lockRatio: true,
+    scaleAlgorithm: "bicubic",
     height: null,
     fps: null,
     videoCodec: "h264",
     audioCodec: "aac",
     videoCodecId: Object.values(VideoCodecs)[0].cpu,
     audioCodecId: Object.values(AudioCodecs)[0].id,
+    mode: "encode",
     limitSize: false,
     crfValue: null,
     targetBitrate: null,
     minBitrate: null,
     maxBitrate: null,
     mergeAudioTracks: [],
+    separateAudioTracks: false,
+    normalizeLoudness: false,
+    loudnessTarget: { integrated: -16, truePeak: -1.5 },
+    audioBitrate: null,
+    sampleRate: null,
+    audioChannels: null,
+    channelLayout: null,
+    fadeIn: 0,
+    fadeOut: 0,
+    subtitleTracks: [],
+    keepChapters: true,
     rateControl: "cbr",
-    sizeLimitDetails: { maxAttempts: 5, maxSize: 0, retryThreshold: 0.1 },
+    twoPass: false,
+    sizeLimitDetails: { maxAttempts: 5, maxSize: 0, retryThreshold: 0.1, autoDownscale: true },
   });
 
   const [supportedCodecs, setSupportedCodecs] = createStore<{ video: Codec<VideoCodec>[]; audio: Codec<AudioCodec>[] }>({
@@ -53,6 +69,9 @@ export default function Export() {
   let formRef!: HTMLFormElement;
   let rateControlSelect!: HTMLSelectElement;
 
+  // Copying keeps the picture and sound as they are, so nothing that changes them can be set
+  const encoding = () => exportInfo.mode === "encode";
+
   createEffect(() => {
     // Set initial default values for new video
 
@@ -66,8 +85,9 @@ export default function Export() {
     setExportInfo("targetBitrate", round((size * 8) / duration / 1000, 0));
     setExportInfo(
       "mergeAudioTracks",
-      streams.filter((stream) => stream.codec_type === "audio").map((stream) => stream.index)
+      streams.filter((stream) => stream.codecType === "audio").map((stream) => stream.index)
     );
+    setExportInfo("subtitleTracks", []);
   });
 
   async function updateAbsolutePath(filepath: string | null, filename: string | null, fileExt: string | null) {
@@ -136,18 +156,25 @@ export default function Export() {
     }
   });
 
-  async function beginRender() {
-    if (!formRef.reportValidity()) return;
+  // Validates the form and builds the settings to render with, or returns null if the export should not go ahead
+  async function collectRenderSettings(): Promise<{ settings: RenderInfo; sizeLimit: RenderSizeLimit | null } | null> {
+    if (!formRef.reportValidity()) return null;
     // Custom check for location
     if (exportInfo.filepath == null) {
       const savePath = await open({ directory: true });
-      if (savePath == null) return;
+      if (savePath == null) return null;
 
       setExportInfo("filepath", savePath);
       localStorage.setItem("export_filepath", savePath);
       await updateAbsolutePath(exportInfo.filepath!, exportInfo.filename, exportInfo.fileExt);
     }
 
+    // Only scale or convert the frame rate when it differs from the source, a locked ratio is kept from the width alone
+    const source = mediaData()!;
+    const widthChanged = exportInfo.width !== source.width;
+    const heightChanged = exportInfo.height !== source.height && !(exportInfo.lockRatio && widthChanged);
+    const fade = (seconds: number) => (encoding() ? seconds : 0);
+
     const settings: RenderInfo = {
       aCodecId: exportInfo.audioCodecId,
       vCodecId: exportInfo.videoCodecId,
@@ -159,10 +186,36 @@ export default function Export() {
       minBitrate: exportInfo.minBitrate || 0,
       overrideFile: false,
       crfValue: exportInfo.crfValue!,
+      twoPass: encoding() && exportInfo.twoPass && TwoPassEncoders.includes(exportInfo.videoCodecId),
       bufSize: (exportInfo.targetBitrate || 0) * 2,
       inputFilepath: videoFile()!,
       outputFilepath: exportInfo.absolutePath!,
+      mode: exportInfo.mode,
       audioTracks: exportInfo.mergeAudioTracks,
+      // Mix the tracks as they're heard in the audio mixer
+      audioMix: audioTracks
+        .filter((track) => track.trackIndex !== -1)
+        .map((track) => ({ index: track.trackIndex, gain: track.muted ? 0 : track.volume, pan: track.pan !== 0 ? track.pan : null })),
+      separateAudioTracks: exportInfo.separateAudioTracks,
+      loudness: encoding() && exportInfo.normalizeLoudness ? { ...exportInfo.loudnessTarget } : null,
+      width: encoding() && widthChanged ? exportInfo.width : null,
+      height: encoding() && heightChanged ? exportInfo.height : null,
+      lockRatio: exportInfo.lockRatio,
+      scaleAlgorithm: exportInfo.scaleAlgorithm,
+      fps: encoding() && exportInfo.fps !== source.fps ? exportInfo.fps : null,
+      audioBitrate: exportInfo.audioBitrate || null,
+      sampleRate: exportInfo.sampleRate || null,
+      audioChannels: exportInfo.audioChannels || null,
+      channelLayout: exportInfo.channelLayout,
+      // Fade both the picture and the sound at the trim points
+      fades: {
+        audioIn: fade(exportInfo.fadeIn),
+        audioOut: fade(exportInfo.fadeOut),
+        videoIn: fade(exportInfo.fadeIn),
+        videoOut: fade(exportInfo.fadeOut),
+      },
+      subtitleTracks: encoding() ? exportInfo.subtitleTracks : exportInfo.subtitleTracks.filter((track) => !track.burnIn),
+      chapters: exportInfo.keepChapters ? source.chapters.map(({ start, end, title }) => ({ start, end, title })) : [],
     };
 
     const fileExists = await exists(settings.outputFilepath);
@@ -174,21 +227,49 @@ export default function Export() {
         okLabel: "Replace",
       });
 
-      if (!overridePrompt) return;
+      if (!overridePrompt) return null;
 
       settings.overrideFile = true;
     }
 
-    render(
-      settings,
-      exportInfo.limitSize
-        ? {
-            maxAttempts: exportInfo.sizeLimitDetails.maxAttempts,
-            maxSize: exportInfo.sizeLimitDetails.maxSize,
-            retryThreshold: exportInfo.sizeLimitDetails.retryThreshold,
-          }
-        : null
-    );
+    const sizeLimit = exportInfo.limitSize
+      ? {
+          maxAttempts: exportInfo.sizeLimitDetails.maxAttempts,
+          maxSize: exportInfo.sizeLimitDetails.maxSize,
+          retryThreshold: exportInfo.sizeLimitDetails.retryThreshold,
+          autoDownscale: exportInfo.sizeLimitDetails.autoDownscale,
+        }
+      : null;
+
+    return { settings, sizeLimit };
+  }
+
+  async function beginRender() {
+    const details = await collectRenderSettings();
+    if (details == null) return;
+
+    render(details.settings, details.sizeLimit);
+  }
+
+  async function addToQueue() {
+    const details = await collectRenderSettings();
+    if (details == null) return;
+
+    const { settings, sizeLimit } = details;
+    try {
+      await invoke<number>("enqueue_render", {
+        settings: {
+          ...settings,
+          trimStart: trim.start,
+          trimEnd: trim.end,
+          trimRanges: trimRanges.length > 0 ? [...trimRanges] : undefined,
+          codecRateControl: VideoCodecs[settings.vCodecName].rateControl[settings.rateControl],
+        },
+        sizeLimit,
+      });
+    } catch (err) {
+      alert(err);
+    }
   }
 
   return (
@@ -243,7 +324,22 @@ export default function Export() {
           </p>
         </fieldset>
         <fieldset class={styles.export__fieldset}>
-          <span style={{ color: "white", "text-align": "center" }}>Video resizing to come...</span>
+          <div class={styles.export__inputGroup}>
+            <label for="render-mode">Mode</label>
+            <select
+              name="render-mode"
+              id="render-mode"
+              onInput={(e) => {
+                setExportInfo("mode", e.target.value as RenderMode);
+                // Copied streams can't be sized to fit a limit
+                if (!encoding()) setExportInfo("limitSize", false);
+              }}
+            >
+              <option value="encode">Re-encode</option>
+              <option value="copy">Copy streams (cuts snap to keyframes)</option>
+              <option value="smart">Smart cut (re-encodes only around the cuts)</option>
+            </select>
+          </div>
           <div class={`${styles.export__group} ${styles.export__video}`}>
             <div class={styles.export__inputGroup} style={{ "grid-area": "x-res" }}>
               <label for="resolution">Width</label>
@@ -252,9 +348,9 @@ export default function Export() {
                 name="width"
                 id="width"
                 min="1"
-                disabled
                 value={exportInfo.width || ""}
                 required
+                disabled={!encoding()}
                 onInput={(e) => setExportInfo("width", e.target.valueAsNumber)}
               />
             </div>
@@ -265,15 +361,32 @@ export default function Export() {
                 name="height"
                 id="height"
                 min="1"
-                disabled
                 value={exportInfo.height || ""}
                 required
+                disabled={!encoding()}
                 onInput={(e) => setExportInfo("height", e.target.valueAsNumber)}
               />
             </div>
             <div class={styles.export__inputGroup} style={{ "grid-area": "lock" }}>
               <label for="lock-aspect">Lock Ratio</label>
-              <input type="checkbox" name="lock-aspect" id="lock-aspect" checked disabled onInput={(e) => setExportInfo("lockRatio", e.target.checked)} />
+              <input type="checkbox" name="lock-aspect" id="lock-aspect" checked={exportInfo.lockRatio} disabled={!encoding()} onInput={(e) => setExportInfo("lockRatio", e.target.checked)} />
+            </div>
+            <div class={styles.export__inputGroup} style={{ "grid-area": "scale" }}>
+              <label for="scale-algorithm">Scaling</label>
+              <select
+                name="scale-algorithm"
+                id="scale-algorithm"
+                disabled={!encoding()}
+                onInput={(e) => setExportInfo("scaleAlgorithm", e.target.value as ScaleAlgorithm)}
+              >
+                <option value="bicubic">Bicubic</option>
+                <option value="bilinear">Bilinear</option>
+                <option value="fast_bilinear">Fast bilinear</option>
+                <option value="lanczos">Lanczos</option>
+                <option value="spline">Spline</option>
+                <option value="area">Area</option>
+                <option value="neighbor">Nearest neighbor</option>
+              </select>
             </div>
             <div class={styles.export__inputGroup} style={{ "grid-area": "fps" }}>
               <label for="fps">Frame Rate</label>
@@ -281,9 +394,9 @@ export default function Export() {
                 type="number"
                 name="fps"
                 id="fps"
-                disabled
                 value={exportInfo.fps || ""}
                 required
+                disabled={!encoding()}
                 onInput={(e) => setExportInfo("fps", e.target.valueAsNumber)}
                 step="0.01"
               />
@@ -336,7 +449,7 @@ export default function Export() {
             </div>
           </div>
         </fieldset>
-        <fieldset class={styles.export__fieldset}>
+        <fieldset class={styles.export__fieldset} disabled={exportInfo.mode === "copy"}>
           <div class={`${styles.export__group}`}>
             <div class={styles.export__inputGroup}>
               <label for="rate-control">Rate control</label>
@@ -426,7 +539,7 @@ export default function Export() {
         <fieldset class={styles.export__fieldset}>
           <div class={styles.export__group}>
             <p style={{ width: "100%" }}>Merge Audio Tracks</p>
-            <For each={mediaData()?.streams.filter((stream) => stream.codec_type === "audio")}>
+            <For each={mediaData()?.streams.filter((stream) => stream.codecType === "audio")}>
               {(stream) => {
                 const id = `audio-track-${stream.index}`;
 
@@ -451,11 +564,190 @@ export default function Export() {
               }}
             </For>
           </div>
+          <div class={styles.export__group}>
+            <label for="separate-audio-tracks">Keep tracks separate</label>
+            <input
+              type="checkbox"
+              name="separate-audio-tracks"
+              id="separate-audio-tracks"
+              checked={exportInfo.separateAudioTracks}
+              onInput={(e) => setExportInfo("separateAudioTracks", e.target.checked)}
+            />
+            <label for="normalize-loudness">Normalize loudness</label>
+            <input
+              type="checkbox"
+              name="normalize-loudness"
+              id="normalize-loudness"
+              checked={exportInfo.normalizeLoudness}
+              disabled={!encoding()}
+              onInput={(e) => setExportInfo("normalizeLoudness", e.target.checked)}
+            />
+          </div>
+          <div class={styles.export__group}>
+            <div class={styles.export__inputGroup}>
+              <label for="loudness-target">Target Loudness (LUFS)</label>
+              <input
+                type="number"
+                name="loudness-target"
+                id="loudness-target"
+                max="0"
+                step="0.1"
+                disabled={!encoding() || !exportInfo.normalizeLoudness}
+                value={exportInfo.loudnessTarget.integrated}
+                onInput={(e) => setExportInfo("loudnessTarget", "integrated", e.target.valueAsNumber)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="true-peak">True Peak (dBTP)</label>
+              <input
+                type="number"
+                name="true-peak"
+                id="true-peak"
+                max="0"
+                step="0.1"
+                disabled={!encoding() || !exportInfo.normalizeLoudness}
+                value={exportInfo.loudnessTarget.truePeak}
+                onInput={(e) => setExportInfo("loudnessTarget", "truePeak", e.target.valueAsNumber)}
+              />
+            </div>
+          </div>
+          <div class={styles.export__group}>
+            <div class={styles.export__inputGroup}>
+              <label for="fade-in">Fade In (s)</label>
+              <input
+                type="number"
+                name="fade-in"
+                id="fade-in"
+                min="0"
+                step="0.1"
+                value={exportInfo.fadeIn}
+                disabled={!encoding()}
+                onInput={(e) => setExportInfo("fadeIn", e.target.valueAsNumber || 0)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="fade-out">Fade Out (s)</label>
+              <input
+                type="number"
+                name="fade-out"
+                id="fade-out"
+                min="0"
+                step="0.1"
+                value={exportInfo.fadeOut}
+                disabled={!encoding()}
+                onInput={(e) => setExportInfo("fadeOut", e.target.valueAsNumber || 0)}
+              />
+            </div>
+          </div>
+          <Show when={mediaData()?.streams.some((stream) => stream.codecType === "subtitle")}>
+            <div class={styles.export__group}>
+              <p style={{ width: "100%" }}>Subtitle Tracks</p>
+              <For each={mediaData()?.streams.filter((stream) => stream.codecType === "subtitle")}>
+                {(stream) => {
+                  const id = `subtitle-track-${stream.index}`;
+
+                  return (
+                    <div class={styles.export__inputGroup}>
+                      <label for={id}>
+                        {stream.index}
+                        {stream.tags.language ? ` (${stream.tags.language})` : ""}
+                      </label>
+                      <select
+                        name={id}
+                        id={id}
+                        onInput={(e) =>
+                          setExportInfo("subtitleTracks", (tracks) => [
+                            ...tracks.filter((track) => track.index !== stream.index),
+                            ...(e.target.value === "none" ? [] : [{ index: stream.index, burnIn: e.target.value === "burn-in" }]),
+                          ])
+                        }
+                      >
+                        <option value="none">Leave out</option>
+                        <option value="copy">Copy</option>
+                        <option value="burn-in" disabled={!encoding()}>
+                          Burn in
+                        </option>
+                      </select>
+                    </div>
+                  );
+                }}
+              </For>
+            </div>
+          </Show>
+          <Show when={mediaData()?.chapters.length}>
+            <div class={styles.export__group}>
+              <label for="keep-chapters">Keep chapters</label>
+              <input
+                type="checkbox"
+                name="keep-chapters"
+                id="keep-chapters"
+                checked={exportInfo.keepChapters}
+                onInput={(e) => setExportInfo("keepChapters", e.target.checked)}
+              />
+            </div>
+          </Show>
+          <div class={styles.export__group}>
+            <div class={styles.export__inputGroup}>
+              <label for="audio-bitrate">Audio Bitrate (Kbps)</label>
+              <input
+                type="number"
+                name="audio-bitrate"
+                id="audio-bitrate"
+                min="1"
+                placeholder="Default"
+                value={exportInfo.audioBitrate || ""}
+                onInput={(e) => setExportInfo("audioBitrate", e.target.valueAsNumber || null)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="sample-rate">Sample Rate (Hz)</label>
+              <input
+                type="number"
+                name="sample-rate"
+                id="sample-rate"
+                min="1"
+                step="1"
+                placeholder="Source"
+                value={exportInfo.sampleRate || ""}
+                onInput={(e) => setExportInfo("sampleRate", e.target.valueAsNumber || null)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="audio-channels">Channels</label>
+              <input
+                type="number"
+                name="audio-channels"
+                id="audio-channels"
+                min="1"
+                step="1"
+                placeholder="Source"
+                value={exportInfo.audioChannels || ""}
+                onInput={(e) => setExportInfo("audioChannels", e.target.valueAsNumber || null)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="channel-layout">Channel Layout</label>
+              <select name="channel-layout" id="channel-layout" onInput={(e) => setExportInfo("channelLayout", e.target.value || null)}>
+                <option value="">Default</option>
+                <option value="mono">Mono</option>
+                <option value="stereo">Stereo</option>
+                <option value="5.1">5.1</option>
+              </select>
+            </div>
+          </div>
         </fieldset>
         <div class={styles.export__inputGroup} style={{ "margin-top": "0.5em" }}>
           <div class={styles.export__group}>
             <label for="limit-size">Limit Size?</label>
-            <input type="checkbox" name="limit-size" id="limit-size" style={{ margin: "0" }} onInput={(e) => setExportInfo("limitSize", e.target.checked)} />
+            <input
+              type="checkbox"
+              name="limit-size"
+              id="limit-size"
+              style={{ margin: "0" }}
+              checked={exportInfo.limitSize}
+              disabled={!encoding()}
+              onInput={(e) => setExportInfo("limitSize", e.target.checked)}
+            />
           </div>
           <span>This will attempt to export the video at the highest bitrate within the size limit.</span>
         </div>
@@ -500,13 +792,35 @@ export default function Export() {
                 onInput={(e) => setExportInfo("sizeLimitDetails", "retryThreshold", e.target.valueAsNumber)}
               />
             </div>
+            <div class={styles.export__inputGroup}>
+              <label for="auto-downscale">Auto downscale</label>
+              <input
+                type="checkbox"
+                name="auto-downscale"
+                id="auto-downscale"
+                checked={exportInfo.sizeLimitDetails.autoDownscale}
+                onInput={(e) => setExportInfo("sizeLimitDetails", "autoDownscale", e.target.checked)}
+              />
+            </div>
+            <div class={styles.export__inputGroup}>
+              <label for="two-pass">Two-pass</label>
+              <input
+                type="checkbox"
+                name="two-pass"
+                id="two-pass"
+                checked={exportInfo.twoPass}
+                disabled={!TwoPassEncoders.includes(exportInfo.videoCodecId)}
+                onInput={(e) => setExportInfo("twoPass", e.target.checked)}
+              />
+            </div>
           </div>
         </fieldset>
       </form>
       <div class={styles.export_btns}>
-        <button class={styles.export__btn} onClick={beginRender}>
-          Export
+        <button class={styles.export__btn} onClick={addToQueue}>
+          Add to Queue
         </button>
+        <button onClick={beginRender}>Export</button>
       </div>
     </Panel>
   );
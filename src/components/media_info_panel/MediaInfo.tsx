@@ -1,6 +1,6 @@
 import { Show, createEffect } from "solid-js";
 
-import { FfprobeOutput, FfprobeVideoStream, MediaData } from "../../../types";
+import { MediaData, MediaInfo as ProbedMediaInfo, ProbeError } from "../../../types";
 import Panel from "../panel/Panel";
 
 import panelStyles from "../panel/PanelCommon.module.css";
@@ -20,6 +20,23 @@ const dateFormatter = new Intl.DateTimeFormat(undefined, {
   second: "numeric",
 });
 
+function describeProbeError(error: ProbeError) {
+  switch (error.kind) {
+    case "notFound":
+      return "The file could not be found.";
+    case "permissionDenied":
+      return "Permission to read the file was denied.";
+    case "invalidData":
+      return "The file is corrupt or is not a video.";
+    case "unsupportedProtocol":
+      return "Files at this location are not supported.";
+    case "invalidOutput":
+      return `FFprobe printed output that could not be read: ${error.message}`;
+    case "other":
+      return `FFprobe failed${error.exitCode != null ? ` with exit code ${error.exitCode}` : ""}: ${error.stderr}`;
+  }
+}
+
 export default function MediaInfo() {
   const [{ videoFile, mediaData }, { setMediaData }, { resetProject }] = useAppContext();
 
@@ -30,29 +47,35 @@ export default function MediaInfo() {
 
     setMediaData(null);
 
-    const rawData = await invoke<string>("ffprobe_cmd", {
-      filepath: file,
-    });
-
+    let info: ProbedMediaInfo;
     try {
-      const json = JSON.parse(rawData) as FfprobeOutput;
+      info = await invoke<ProbedMediaInfo>("ffprobe_cmd", {
+        filepath: file,
+      });
+    } catch (err) {
+      console.error(err);
+      alert(`Could not read ${file}. ${describeProbeError(err as ProbeError)}`);
+      resetProject();
 
-      const videoStream = json.streams.find((stream) => stream.codec_type === "video") as FfprobeVideoStream | undefined;
+      return;
+    }
 
-      if (videoStream == null) {
-        alert(`Please input a file with video. The current file (${json.format.filename}) does not have a video track.`);
+    try {
+      const videoStream = info.streams.find((stream) => stream.codecType === "video");
+
+      if (videoStream == null || videoStream.width == null || videoStream.height == null) {
+        alert(`Please input a file with video. The current file (${info.filename}) does not have a video track.`);
         resetProject();
 
         return;
       }
 
-      const fpsFraction = videoStream.avg_frame_rate.split("/");
       const aspectRatioGcd = gcd(videoStream.width, videoStream.height);
-      const size = Number(json.format.size);
+      const size = info.size ?? (await stat(file)).size;
 
       const fileExt = await path.extname(file);
 
-      const created = json.format.tags.creation_time || (await stat(file)).birthtime || 0;
+      const created = info.tags.creation_time || (await stat(file)).birthtime || 0;
 
       const data: MediaData = {
         filepath: file,
@@ -60,15 +83,16 @@ export default function MediaInfo() {
         fileExt,
         width: videoStream.width,
         height: videoStream.height,
-        videoCodec: videoStream.codec_name,
-        fps: round(Number(fpsFraction[0]) / Number(fpsFraction[1])),
-        streams: json.streams,
+        videoCodec: videoStream.codecName ?? "Unknown",
+        fps: round(videoStream.avgFrameRate ?? videoStream.rFrameRate ?? 0),
+        streams: info.streams,
+        chapters: info.chapters,
         aspectRatioX: round(videoStream.width / aspectRatioGcd),
         aspectRatioY: round(videoStream.height / aspectRatioGcd),
         dateCreated: created instanceof Date ? created : new Date(created),
         size,
         size_mb: round(size / 1e6),
-        duration: round(Number(videoStream.duration), 3),
+        duration: round(videoStream.duration ?? info.duration ?? 0, 3),
       };
 
       setMediaData(data);
@@ -108,6 +132,22 @@ export default function MediaInfo() {
                   <span class={styles.media_info__text}>Total Streams</span>
                   <span class={`force-wrap ${styles.media_info__text}`}>{data.streams.length}</span>
                 </li>
+                <li class={styles.media_info__item}>
+                  <span class={styles.media_info__text}>Subtitle Tracks</span>
+                  <span class={`force-wrap ${styles.media_info__text}`}>
+                    {data.streams.filter((stream) => stream.codecType === "subtitle").length}
+                  </span>
+                </li>
+                <li class={styles.media_info__item}>
+                  <span class={styles.media_info__text}>Attachments</span>
+                  <span class={`force-wrap ${styles.media_info__text}`}>
+                    {data.streams.filter((stream) => stream.codecType === "attachment").length}
+                  </span>
+                </li>
+                <li class={styles.media_info__item}>
+                  <span class={styles.media_info__text}>Chapters</span>
+                  <span class={`force-wrap ${styles.media_info__text}`}>{data.chapters.length}</span>
+                </li>
                 <li class={styles.media_info__item}>
                   <span class={styles.media_info__text}>Aspect Ratio</span>
                   <span class={`force-wrap ${styles.media_info__text}`}>
@@ -1,4 +1,4 @@
-import { createEffect, createSignal, onCleanup, onMount } from "solid-js";
+import { createEffect, createSignal, For, onCleanup, onMount } from "solid-js";
 import Panel from "../panel/Panel";
 
 import styles from "./Timeline.module.css";
@@ -11,9 +11,10 @@ import Menubar from "../../classes/Menubar";
 
 const capturingElements = new Set(["INPUT", "SELECT", "BUTTON"]);
 const partialCapturingEvents = new Set(["Space"]);
+const WAVEFORM_BUCKETS = 1000; // Peaks drawn across the timeline
 
 export default function Timeline() {
-  const [{ videoElement, mediaData, trim }, { setTrim }] = useAppContext();
+  const [{ videoElement, videoFile, mediaData, trim, trimRanges }, { setTrim, setTrimRanges }] = useAppContext();
   const [{ currentTime, playing }, { setCurrentTime, video }] = usePlayerContext();
 
   const [dragging, setDragging] = createSignal(false);
@@ -22,8 +23,10 @@ export default function Timeline() {
   const [trimPos, setTrimPos] = createStore<TrimRange>({ start: 0, end: 1 });
   const [trimStartTime, setTrimStartTime] = createSignal<number | null>(null);
   const [timecodeType, setTimecodeType] = createSignal<"frames" | "time">("frames");
+  const [thumbnailStrip, setThumbnailStrip] = createSignal<string | null>(null);
 
   let timelineBar: HTMLDivElement;
+  let waveformCanvas: HTMLCanvasElement;
 
   function handleKeydown(event: KeyboardEvent) {
     if (dragging()) return;
@@ -182,6 +185,55 @@ export default function Timeline() {
     if (duration == null) return;
 
     setTrim("end", duration);
+    // Ranges kept from the previous video don't apply to this one
+    setTrimRanges([]);
+  });
+
+  function keepRange() {
+    if (!isFinite(trim.end) || trim.end <= trim.start) return;
+
+    setTrimRanges(trimRanges.length, { start: trim.start, end: trim.end });
+  }
+
+  function removeRange(index: number) {
+    setTrimRanges((ranges) => ranges.filter((_, i) => i !== index));
+  }
+
+  createEffect(async () => {
+    // Draw the peaks of the first audio track behind the timeline
+    const video = videoFile();
+    const stream = mediaData()?.streams.find((stream) => stream.codecType === "audio");
+    const context = waveformCanvas.getContext("2d")!;
+    context.clearRect(0, 0, waveformCanvas.width, waveformCanvas.height);
+    if (video == null || stream == null) return;
+
+    const response = await fetch(
+      `${location.protocol}//waveform.${location.hostname}/${encodeURIComponent(video)}/${stream.index}?buckets=${WAVEFORM_BUCKETS}&format=binary`
+    );
+    if (!response.ok || video !== videoFile()) return;
+    const peaks = new Int16Array(await response.arrayBuffer()); // Interleaved min/max pairs
+
+    const { width, height } = waveformCanvas;
+    const barWidth = width / WAVEFORM_BUCKETS;
+    context.fillStyle = getComputedStyle(waveformCanvas).color;
+    for (let i = 0; i < peaks.length / 2; i++) {
+      const top = ((1 - peaks[i * 2 + 1] / 32768) * height) / 2;
+      const bottom = ((1 - peaks[i * 2] / 32768) * height) / 2;
+      context.fillRect(i * barWidth, top, barWidth, Math.max(bottom - top, 1));
+    }
+  });
+
+  createEffect(() => {
+    // Fill the timeline with frames from across the video, as many as fit at the video's aspect ratio
+    const video = videoFile();
+    const data = mediaData();
+    if (video == null || data == null || data.width === 0 || data.height === 0) return setThumbnailStrip(null);
+
+    const { width, height } = timelineBar.getBoundingClientRect();
+    const count = Math.max(1, Math.min(200, Math.round(width / ((height * data.width) / data.height))));
+    setThumbnailStrip(
+      `${location.protocol}//thumbnail.${location.hostname}/${encodeURIComponent(video)}/sprite?count=${count}&columns=${count}&width=160`
+    );
   });
 
   function resetTimeline() {
@@ -234,7 +286,12 @@ export default function Timeline() {
         </div>
         <div class={styles.timeline__controls}>
           <div class={styles.timeline__container}>
-            <div class={styles.timeline__bar} ref={(ref) => (timelineBar = ref)}>
+            <div
+              class={styles.timeline__bar}
+              ref={(ref) => (timelineBar = ref)}
+              style={thumbnailStrip() != null ? `background-image: url("${thumbnailStrip()}")` : undefined}
+            >
+              <canvas class={styles.timeline__waveform} width={WAVEFORM_BUCKETS} height={64} ref={(ref) => (waveformCanvas = ref)}></canvas>
               <div class={styles.timeline__scrollbar} tabIndex={0} role="slider" aria-label="Seek slider" onPointerDown={handleCursorDown}>
                 <div
                   class={`${styles.timeline__cursor} ${styles.timeline__playhead}`}
@@ -265,7 +322,21 @@ export default function Timeline() {
             </div>
           </div>
         </div>
-        <div class={styles.timeline__btns}></div>
+        <div class={styles.timeline__btns}>
+          <button type="button" title="Kept ranges are joined in order into the export" onClick={keepRange}>
+            Keep range
+          </button>
+          <For each={trimRanges}>
+            {(range, i) => (
+              <button type="button" title="Remove range" onClick={() => removeRange(i())}>
+                {round(range.start)}s - {round(range.end)}s ✕
+              </button>
+            )}
+          </For>
+          <button type="button" disabled={trimRanges.length === 0} onClick={() => setTrimRanges([])}>
+            Clear ranges
+          </button>
+        </div>
       </div>
     </Panel>
   );
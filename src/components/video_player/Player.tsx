@@ -1,4 +1,4 @@
-import { convertFileSrc } from "@tauri-apps/api/core";
+import { Channel, convertFileSrc, invoke } from "@tauri-apps/api/core";
 
 import Panel from "../panel/Panel";
 
@@ -7,12 +7,15 @@ import { usePlayerContext } from "../../contexts/PlayerContext";
 import { useAppContext } from "../../contexts/AppContext";
 import { Show, createEffect, createSignal, onMount } from "solid-js";
 import { createAudioAnalyser } from "../audio_panel/AudioMixer";
+import { PreviewProgress, PreviewSource } from "../../../types";
 
 export default function Player() {
   const [{ videoElement, videoFile, trim }, { setVideoElement }] = useAppContext();
   const [{ playing, audioContext }, { setCurrentTime, setPlaying, setAudioTracks, video }] = usePlayerContext();
 
   const [canPlay, setCanPlay] = createSignal<null | boolean>(null);
+  const [videoSrc, setVideoSrc] = createSignal("");
+  const [previewProgress, setPreviewProgress] = createSignal<number | null>(null); // Progress of the proxy transcode, if making one
 
   function updateTime() {
     const seconds = videoElement()!.currentTime;
@@ -33,13 +36,16 @@ export default function Player() {
   }
 
   onMount(() => {
-    const { computeAmplitude, source } = createAudioAnalyser(audioContext, videoElement()!);
+    const { computeAmplitude, source, panner } = createAudioAnalyser(audioContext, videoElement()!);
 
     setAudioTracks(0, {
       trackIndex: -1,
       muted: false,
+      volume: 1,
+      pan: 0,
       getCurrentAmplitude: computeAmplitude,
       sourceNode: source,
+      pannerNode: panner,
       sourceElement: videoElement(),
     });
   });
@@ -50,17 +56,51 @@ export default function Player() {
     }
   });
 
+  createEffect(async () => {
+    // Files the player can't decode are played through a low resolution proxy made by the backend
+    const file = videoFile();
+    setVideoSrc("");
+    setPreviewProgress(null);
+    if (file == null) return;
+
+    const onProgress = new Channel<PreviewProgress>();
+    onProgress.onmessage = (event) => {
+      if (file === videoFile()) setPreviewProgress(event.type === "transcoding" ? event.progress : null);
+    };
+
+    // Web views that play HLS can start straight away, transcoding only around the playhead
+    const segmented = videoElement()!.canPlayType("application/vnd.apple.mpegurl") !== "";
+
+    try {
+      const source = await invoke<PreviewSource>("prepare_preview", { filepath: file, segmented, onProgress });
+      if (file !== videoFile()) return;
+
+      const previewUrl = `${location.protocol}//preview.${location.hostname}/${encodeURIComponent(file)}`;
+      if (source === "proxy") setVideoSrc(previewUrl);
+      else if (source === "segmented") setVideoSrc(`${previewUrl}/playlist.m3u8`);
+      else setVideoSrc(convertFileSrc(file));
+    } catch (err) {
+      // Let the player try the file as it is
+      console.error(err);
+      if (file === videoFile()) setVideoSrc(convertFileSrc(file));
+    }
+    setPreviewProgress(null);
+  });
+
   return (
     <Panel class={styles.player} column>
       <div class={styles.player__container}>
         <Show when={canPlay() != null && !canPlay()}>
           <img src="/images/media_pending.png" alt="Media pending" class={styles.player__poster} />
         </Show>
+        <Show when={previewProgress() != null}>
+          <p class={styles.player__previewProgress}>Creating preview: {Math.round(previewProgress()! * 100)}%</p>
+        </Show>
         <video
           class={styles.player__video}
           preload="auto"
           onCanPlay={() => setCanPlay(true)}
-          src={videoFile() != null ? convertFileSrc(videoFile()!) : ""}
+          src={videoSrc()}
           ref={(ref) => setVideoElement(ref)}
           onPause={() => setPlaying(false)}
           onPlay={() => {
@@ -11,6 +11,8 @@ function createAppContext() {
   const [videoFile, setVideoFile] = createSignal<string | null>(null);
   const [mediaData, setMediaData] = createSignal<MediaData | null>(null);
   const [trim, setTrim] = createStore<TrimRange>({ start: 0, end: Infinity });
+  // Ranges kept from the timeline, joined in order into the export instead of the trim
+  const [trimRanges, setTrimRanges] = createStore<TrimRange[]>([]);
   const [renderData, setRenderData] = createStore<{ rendering: boolean; renderer: Renderer | null }>({
     rendering: false,
     renderer: null,
@@ -20,8 +22,8 @@ function createAppContext() {
   function resetProject() {}
 
   return [
-    { videoElement, videoFile, mediaData, renderData, trim },
-    { setVideoElement, setVideoFile, setMediaData, setTrim, setRenderData },
+    { videoElement, videoFile, mediaData, renderData, trim, trimRanges },
+    { setVideoElement, setVideoFile, setMediaData, setTrim, setTrimRanges, setRenderData },
     { render, resetProject },
   ] as const;
 }
@@ -46,6 +48,8 @@ export default function AppProvider(props: AppProvider) {
   const [videoFile, setVideoFile] = createSignal<string | null>(null);
   const [mediaData, setMediaData] = createSignal<MediaData | null>(null);
   const [trim, setTrim] = createStore<TrimRange>({ start: 0, end: Infinity });
+  // Ranges kept from the timeline, joined in order into the export instead of the trim
+  const [trimRanges, setTrimRanges] = createStore<TrimRange[]>([]);
   const [renderData, setRenderData] = createStore<{ rendering: boolean; renderer: Renderer | null }>({
     rendering: false,
     renderer: null,
@@ -56,6 +60,7 @@ export default function AppProvider(props: AppProvider) {
     setVideoFile(null);
     setMediaData(null);
     setTrim({ start: 0, end: Infinity });
+    setTrimRanges([]);
   }
 
   async function handleKeydown(event: KeyboardEvent) {
@@ -101,10 +106,11 @@ export default function AppProvider(props: AppProvider) {
         ...settings,
         trimStart: trim.start,
         trimEnd: trim.end,
+        trimRanges: trimRanges.length > 0 ? [...trimRanges] : undefined,
       },
       sizeLimit,
       {
-        totalDuration: trim.end - trim.start,
+        totalDuration: trimRanges.length > 0 ? trimRanges.reduce((total, range) => total + range.end - range.start, 0) : trim.end - trim.start,
       }
     );
 
@@ -119,10 +125,16 @@ export default function AppProvider(props: AppProvider) {
     alert("Preferences: coming soon");
   }
 
+  async function clearMediaCache() {
+    const freed = await invoke<number>("clear_media_cache");
+    alert(`Cleared ${(freed / 1e6).toFixed(1)} MB of cached audio`);
+  }
+
   onMount(async () => {
     await Menubar.init();
     Menubar.addEventListener("new_proj", resetProject);
     Menubar.addEventListener("prefs", openPreferences);
+    Menubar.addEventListener("clear_cache", clearMediaCache);
     Menubar.addEventListener("zoom_in", zoomIn);
     Menubar.addEventListener("zoom_out", zoomOut);
 
@@ -139,8 +151,8 @@ export default function AppProvider(props: AppProvider) {
   return (
     <AppContext.Provider
       value={[
-        { videoElement, videoFile, mediaData, renderData, trim },
-        { setVideoElement, setVideoFile, setMediaData, setRenderData, setTrim },
+        { videoElement, videoFile, mediaData, renderData, trim, trimRanges },
+        { setVideoElement, setVideoFile, setMediaData, setRenderData, setTrim, setTrimRanges },
         { render, resetProject },
       ]}
     >
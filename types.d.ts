@@ -13,163 +13,72 @@ export type MediaData = {
   aspectRatioX: number;
   aspectRatioY: number;
   dateCreated: Date;
-  streams: (FfprobeVideoStream | FfprobeAudioStream)[];
+  streams: StreamInfo[];
+  chapters: ChapterInfo[];
   size: number;
   size_mb: number;
   duration: number;
 };
 
-export type FfprobeVideoStream = {
-  index: number;
-  codec_name: string;
-  codec_long_name: string;
-  profile?: string;
-  codec_type: string;
-  codec_tag_string: string;
-  codec_tag: string;
-  width: number;
-  height: number;
-  coded_width?: number;
-  coded_height?: number;
-  closed_captions?: 0 | 1;
-  film_grain: 0 | 1;
-  has_b_frames: 0 | 1;
-  sample_aspect_ratio?: string;
-  display_aspect_ratio?: string;
-  pix_fmt: string;
-  level: number;
-  color_range: string;
-  color_space: string;
-  color_transfer: string;
-  color_primaries: string;
-  chroma_location: string;
-  field_order: string;
-  refs: number;
-  is_avc: "true" | "false";
-  nal_length_size: string;
-  id: string;
-  r_frame_rate: string;
-  avg_frame_rate: string;
-  time_base: string;
-  start_pts: number;
-  start_time: string;
-  duration_ts: 4057390;
-  duration: string;
-  bit_rate: string;
-  bits_per_raw_sample: string;
-  nb_frames: string;
-  extradata_size: 42;
-  disposition: {
-    default: 0 | 1;
-    dub: 0 | 1;
-    original: 0 | 1;
-    comment: 0 | 1;
-    lyrics: 0 | 1;
-    karaoke: 0 | 1;
-    forced: 0 | 1;
-    hearing_impaired: 0 | 1;
-    visual_impaired: 0 | 1;
-    clean_effects: 0 | 1;
-    attached_pic: 0 | 1;
-    timed_thumbnails: 0 | 1;
-    non_diegetic: 0 | 1;
-    captions: 0 | 1;
-    descriptions: 0 | 1;
-    metadata: 0 | 1;
-    dependent: 0 | 1;
-    still_image: 0 | 1;
-  };
-  tags: {
-    creation_time?: string;
-    language: string;
-    handler_name: string;
-    vendor_id: string;
-  };
-};
-
-export type FfprobeAudioStream = {
-  avg_frame_rate: "0/0";
-  bit_rate: "192013";
-  bits_per_sample: number;
-  channel_layout: "stereo" | "mono";
-  channels: number;
-  codec_long_name: string;
-  codec_name: string;
-  codec_tag: string;
-  codec_tag_string: string;
-  codec_type: string;
-  disposition: {
-    attached_pic: 0 | 1;
-    captions: 0 | 1;
-    clean_effects: 0 | 1;
-    comment: 0 | 1;
-    default: 0 | 1;
-    dependent: 0 | 1;
-    descriptions: 0 | 1;
-    dub: 0 | 1;
-    forced: 0 | 1;
-    hearing_impaired: 0 | 1;
-    karaoke: 0 | 1;
-    lyrics: 0 | 1;
-    metadata: 0 | 1;
-    non_diegetic: 0 | 1;
-    original: 0 | 1;
-    still_image: 0 | 1;
-    timed_thumbnails: 0 | 1;
-    visual_impaired: 0 | 1;
-  };
-  duration: string;
-  duration_ts: number;
-  extradata_size: number;
-  id: string;
+export type StreamKind = "video" | "audio" | "subtitle" | "attachment" | "data" | "unknown";
+
+// Numbers FFPROBE doesn't know are null, times are in seconds and bitrates in b/s
+export type StreamInfo = {
   index: number;
-  initial_padding: number;
-  nb_frames: string;
-  profile: string;
-  r_frame_rate: string;
-  sample_fmt: string;
-  sample_rate: string;
-  start_pts: number;
-  start_time: string;
-  tags: {
-    creation_time: string;
-    handler_name: string;
-    language: string;
-    vendor_id: string;
-  };
-
-  time_base: string;
-};
-
-export type FfprobeOutput = {
-  streams: (FfprobeVideoStream | FfprobeAudioStream)[];
-  format: {
-    filename: string;
-    nb_streams: number;
-    nb_programs: number;
-    nb_stream_groups: number;
-    format_name: string;
-    format_long_name: string;
-    start_time: string;
-    duration: string;
-    size: string;
-    bit_rate: string;
-    probe_score: number;
-    tags: {
-      major_brand: string;
-      minor_version: string;
-      encoder?: string;
-      compatible_brands: string;
-      creation_time?: string;
-      date?: string;
-    };
-  };
+  codecType: StreamKind;
+  codecName: string | null;
+  codecLongName: string | null;
+  profile: string | null;
+  startTime: number | null;
+  duration: number | null;
+  bitRate: number | null;
+  frameCount: number | null;
+  width: number | null;
+  height: number | null;
+  pixFmt: string | null;
+  displayAspectRatio: string | null;
+  avgFrameRate: number | null;
+  rFrameRate: number | null;
+  sampleRate: number | null;
+  channels: number | null;
+  channelLayout: string | null;
+  disposition: Record<string, boolean>;
+  tags: Record<string, string>;
+};
+
+export type ChapterInfo = {
+  id: number;
+  start: number;
+  end: number;
+  title: string | null;
+};
+
+export type MediaInfo = {
+  filename: string;
+  formatName: string;
+  formatLongName: string | null;
+  startTime: number | null;
+  duration: number | null;
+  size: number | null;
+  bitRate: number | null;
+  tags: Record<string, string>;
+  streams: StreamInfo[];
+  chapters: ChapterInfo[];
 };
 
+// Why FFPROBE couldn't read a file, stderr is what it printed
+export type ProbeError =
+  | { kind: "notFound" | "permissionDenied" | "invalidData" | "unsupportedProtocol"; stderr: string }
+  | { kind: "invalidOutput"; message: string }
+  | { kind: "other"; exitCode: number | null; stderr: string };
+
 export type AudioTrack = {
   trackIndex: number;
   muted: boolean;
+  volume: number; // 0-1, also used as the track's gain on export
+  pan: number; // -1 (left) to 1 (right), also used on export
   sourceNode: MediaElementAudioSourceNode;
+  pannerNode: StereoPannerNode;
   sourceElement: HTMLMediaElement;
   getCurrentAmplitude: () => number;
 };
@@ -190,13 +99,27 @@ export type ExportInfo = {
   width: number | null;
   height: number | null;
   lockRatio: boolean;
+  scaleAlgorithm: ScaleAlgorithm;
   fps: number | null;
   videoCodec: VideoCodec;
   audioCodec: AudioCodec;
   videoCodecId: string;
   audioCodecId: string;
+  mode: RenderMode;
 
   mergeAudioTracks: number[];
+  separateAudioTracks: boolean;
+  normalizeLoudness: boolean;
+  loudnessTarget: LoudnessTarget;
+  audioBitrate: number | null; // Kb/s
+  sampleRate: number | null;
+  audioChannels: number | null;
+  channelLayout: string | null;
+  fadeIn: number; // Seconds
+  fadeOut: number;
+
+  subtitleTracks: SubtitleTrack[];
+  keepChapters: boolean;
 
   rateControl: RateControlType;
   targetBitrate: number | null;
@@ -204,6 +127,8 @@ export type ExportInfo = {
   minBitrate: number | null;
   crfValue: number | null;
 
+  twoPass: boolean;
+
   limitSize: boolean;
   sizeLimitDetails: RenderSizeLimit;
 };
@@ -212,20 +137,25 @@ export type RenderSizeLimit = {
   maxSize: number; // MB
   maxAttempts: number;
   retryThreshold: number;
+  autoDownscale?: boolean; // Lower the resolution/fps when the size limit is too small for the source
 };
 
 export type RenderInfo = {
   inputFilepath: string;
   outputFilepath: string;
-  // width: number;
-  // height: number;
-  // fps: number;
+  width: number | null;
+  height: number | null;
+  lockRatio: boolean;
+  scaleAlgorithm?: ScaleAlgorithm;
+  fps: number | null;
   rateControl: RateControlType;
   targetBitrate: number;
   maxBitrate: number;
   minBitrate: number;
   bufSize: number;
   crfValue: number;
+  twoPass: boolean;
+  mode?: RenderMode;
   overrideFile: boolean;
 
   vCodecName: VideoCodec;
@@ -233,21 +163,124 @@ export type RenderInfo = {
   vCodecId: string;
   aCodecId: string;
   audioTracks: number[];
+  audioMix?: AudioTrackMix[];
+  separateAudioTracks?: boolean; // Keep each track as its own stream instead of mixing them
+  loudness?: LoudnessTarget | null; // Normalise the audio to this loudness
+  channelLayout?: string | null; // e.g. "mono" or "5.1", several tracks are mixed to stereo if null
+  audioBitrate?: number | null; // Kb/s, the encoder's default if null
+  sampleRate?: number | null;
+  audioChannels?: number | null;
+  fades?: Fades;
+  subtitleTracks?: SubtitleTrack[];
+  chapters?: Chapter[]; // Cut and joined like the trim, the input's chapters are left out if empty
+};
+
+export type SubtitleTrack = {
+  index: number;
+  burnIn?: boolean; // Draw onto the video instead of keeping as a stream
+};
+
+export type Chapter = {
+  start: number; // Seconds
+  end: number;
+  title?: string | null;
+};
+
+export type Fades = {
+  // Seconds, relative to the trimmed output. 0 leaves the fade out
+  audioIn?: number;
+  audioOut?: number;
+  videoIn?: number; // Video fades from and to black
+  videoOut?: number;
+};
+
+export type AudioTrackMix = {
+  index: number;
+  gain: number; // Linear multiplier
+  pan?: number | null; // -1 (left) to 1 (right)
+  codecId?: string | null; // Only used when tracks are kept separate
+  bitrate?: number | null; // Kb/s, only used when tracks are kept separate
 };
 
+export type ScaleAlgorithm = "fast_bilinear" | "bilinear" | "bicubic" | "neighbor" | "area" | "lanczos" | "spline";
+
+export type RenderMode = "encode" | "copy" | "smart"; // Copying snaps trims to keyframes, smart only re-encodes the GOPs at the cuts
+
 export type RenderSettings = {
   trimStart: number;
   trimEnd: number;
+  trimRanges?: TrimRange[]; // Concatenated into the output instead of trimStart-trimEnd
 } & RenderInfo;
 
 export type RenderMeta = {
   totalDuration: number;
 };
 
-export type RawProgress = {
-  out_time_us: number;
-  fps: number;
-  speed: number;
-  progress: "continue" | "end";
-  bitrate: number;
+export type RenderProgress =
+  | { type: "trimSnapped"; ranges: TrimRange[] }
+  | { type: "downscaled"; width: number; height: number; fps: number; bitsPerPixel: number }
+  | { type: "measuringLoudness"; track: number }
+  | { type: "started"; attempt: number; pass: number | null }
+  | {
+      type: "progress";
+      outTimeUs: number | null;
+      fps: number | null;
+      speed: number | null;
+      bitrate: number | null; // Kb/s
+      totalSize: number | null;
+      frame: number | null;
+    }
+  | { type: "attemptFinished"; attempt: number; targetBitrate: number; size: number }
+  | { type: "finished"; outputSize: number; loudness: LoudnessMeasurement[] }
+  | { type: "cancelled" }
+  | { type: "failed"; exitStatus: number | null; stderrTail: string };
+
+export type LoudnessTarget = {
+  integrated: number; // LUFS
+  truePeak: number; // dBTP
+  loudnessRange?: number; // LU
+};
+
+export type LoudnessMeasurement = {
+  integrated: number; // LUFS
+  truePeak: number; // dBTP
+  loudnessRange: number; // LU
+  threshold: number;
+  offset: number;
+};
+
+export type PreviewSource = "original" | "proxy" | "segmented"; // Proxies are played from preview://, segmented previews from its playlist.m3u8
+
+export type PreviewProgress = { type: "transcoding"; progress: number } | { type: "ready" };
+
+export type RenderStatus = {
+  id: number;
+  inputFilepath: string;
+  outputFilepath: string;
+  attempt: number;
+  pass: number | null;
+  progress: RenderProgress | null;
+};
+
+export type QueueJobState =
+  | { type: "queued" }
+  | { type: "paused" }
+  | { type: "starting" }
+  | { type: "rendering"; taskId: number }
+  | { type: "finished"; outputSize: number }
+  | { type: "failed"; message: string }
+  | { type: "cancelled" };
+
+export type QueueJob = {
+  id: number;
+  settings: RenderSettings & { codecRateControl: string[] };
+  sizeLimit: RenderSizeLimit | null;
+  state: QueueJobState;
+};
+
+export type RenderQueue = {
+  jobs: QueueJob[];
+  concurrency: number;
+  paused: boolean;
+  nextJobId: number;
 };